@@ -0,0 +1,176 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::fairness::to_hex;
+use crate::store;
+
+/// PBKDF2 rounds used to hash passwords.
+const HASH_ROUNDS: usize = 10_000;
+
+#[derive(Debug)]
+pub enum AccountError {
+    NameTaken,
+    WrongPassword,
+    Io(io::Error),
+}
+
+impl From<io::Error> for AccountError {
+    fn from(err: io::Error) -> AccountError {
+        AccountError::Io(err)
+    }
+}
+
+/// The account a player is logged in with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub id: u64,
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Account {
+    id: u64,
+    username: String,
+    /// Salt and hash of the password, accounts without one can only be used
+    /// with their token.
+    password: Option<(String, String)>,
+    /// Hashes of the tokens issued for this account.
+    tokens: Vec<String>,
+}
+
+/// All player accounts, saved to a JSON file after every change if the
+/// server has one.
+#[derive(Debug, Default)]
+pub struct Accounts {
+    path: Option<PathBuf>,
+    accounts: Mutex<Vec<Account>>,
+}
+
+impl Accounts {
+    /// Loads the accounts from `path`, which is created on the first change
+    /// if it doesn't exist yet.
+    pub fn open(path: PathBuf) -> io::Result<Accounts> {
+        let accounts = store::load(&path)?;
+        Ok(Accounts {
+            path: Some(path),
+            accounts: Mutex::new(accounts),
+        })
+    }
+
+    /// Creates an account and returns its id and a token for it.
+    pub fn register(
+        &self,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<(u64, String), AccountError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts
+            .iter()
+            .any(|a| a.username.eq_ignore_ascii_case(username))
+        {
+            Err(AccountError::NameTaken)?
+        }
+        let password = password.map(|password| {
+            let salt = format!("{:032x}", rand::random::<u128>());
+            let hash = hash_password(password, &salt);
+            (salt, hash)
+        });
+        let token = new_token();
+        let id = accounts.iter().map(|a| a.id).max().unwrap_or(0) + 1;
+        accounts.push(Account {
+            id,
+            username: username.to_owned(),
+            password,
+            tokens: vec![hash_token(&token)],
+        });
+        self.save(&accounts)?;
+        Ok((id, token))
+    }
+
+    /// Issues a new token for the account, if the password is right.
+    pub fn login(&self, username: &str, password: &str) -> Result<(u64, String), AccountError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts
+            .iter_mut()
+            .find(|a| a.username.eq_ignore_ascii_case(username))
+            .ok_or(AccountError::WrongPassword)?;
+        match &account.password {
+            Some((salt, hash)) if *hash == hash_password(password, salt) => {}
+            _ => Err(AccountError::WrongPassword)?,
+        }
+        let token = new_token();
+        account.tokens.push(hash_token(&token));
+        let id = account.id;
+        self.save(&accounts)?;
+        Ok((id, token))
+    }
+
+    /// Returns who `token` belongs to.
+    pub fn authenticate(&self, token: &str) -> Option<Identity> {
+        let hash = hash_token(token);
+        let accounts = self.accounts.lock().unwrap();
+        let account = accounts.iter().find(|a| a.tokens.contains(&hash))?;
+        Some(Identity {
+            id: account.id,
+            username: account.username.clone(),
+        })
+    }
+
+    pub fn username(&self, id: u64) -> Option<String> {
+        let accounts = self.accounts.lock().unwrap();
+        let account = accounts.iter().find(|a| a.id == id)?;
+        Some(account.username.clone())
+    }
+
+    fn save(&self, accounts: &[Account]) -> io::Result<()> {
+        match &self.path {
+            Some(path) => store::save(path, accounts),
+            None => Ok(()),
+        }
+    }
+}
+
+fn new_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn hash_password(password: &str, salt: &str) -> String {
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt.as_bytes(), HASH_ROUNDS, &mut hash);
+    to_hex(&hash)
+}
+
+/// Tokens are random enough that a plain hash keeps them safe at rest.
+fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_logs_in_with_passwords_and_tokens() {
+        let accounts = Accounts::default();
+        let (id, token) = accounts.register("Alice", Some("secret")).unwrap();
+        assert!(matches!(
+            accounts.register("alice", None),
+            Err(AccountError::NameTaken)
+        ));
+        assert_eq!(accounts.authenticate(&token).unwrap().id, id);
+
+        assert!(accounts.login("Alice", "wrong").is_err());
+        let (_, token) = accounts.login("alice", "secret").unwrap();
+        assert_eq!(accounts.authenticate(&token).unwrap().username, "Alice");
+        assert!(accounts.authenticate("unknown").is_none());
+
+        let (_, token) = accounts.register("Bob", None).unwrap();
+        assert!(accounts.login("Bob", "").is_err());
+        assert!(accounts.authenticate(&token).is_some());
+    }
+}
@@ -1,14 +1,35 @@
-use crate::cards::{Card, Rank};
-use crate::game::{GameState, HousePile, PlayerAction, PlayerPile};
+use serde::{Deserialize, Serialize};
+
+use crate::cards::{Card, Rank, Suit};
+use crate::game::{GameState, HousePile, PlayerAction, PlayerPile, TurnState};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// Never attacks.
+    Easy,
+    #[default]
+    Normal,
+    /// Only attacks piles it beats.
+    Hard,
+}
 
 #[derive(Clone, Debug)]
 pub struct AIPlayer {
     player_id: usize,
+    difficulty: Difficulty,
 }
 
 impl AIPlayer {
     pub fn new(player_id: usize) -> AIPlayer {
-        AIPlayer { player_id }
+        AIPlayer::with_difficulty(player_id, Difficulty::Normal)
+    }
+
+    pub fn with_difficulty(player_id: usize, difficulty: Difficulty) -> AIPlayer {
+        AIPlayer {
+            player_id,
+            difficulty,
+        }
     }
 
     fn evaluate_card(&self, state: &GameState, card: Card) -> Vec<f32> {
@@ -88,43 +109,70 @@ impl AIPlayer {
         }
     }
 
-    pub fn play_turn(&mut self, mut state: GameState) -> Vec<PlayerAction> {
+    pub fn play_turn(&mut self, state: GameState) -> Vec<PlayerAction> {
+        self.plan_turn(state)
+            .into_iter()
+            .map(|(action, _)| action)
+            .collect()
+    }
+
+    /// The action the AI would take next in place of the player, with a
+    /// short explanation of why. The AI only looks at the piles on the table
+    /// and the player's own hand, so this gives nothing away.
+    pub fn hint(&self, state: GameState) -> (PlayerAction, String) {
+        self.plan_turn(state).remove(0)
+    }
+
+    /// Every action of the turn, each with the reason for it.
+    fn plan_turn(&self, mut state: GameState) -> Vec<(PlayerAction, String)> {
         let mut actions = Vec::new();
 
-        // Do attacks:
-        let attack_piles = state.players[self.player_id]
-            .house_piles()
-            .into_iter()
-            .filter_map(|(idx, pile)| {
-                let value = self.evaluate_house_pile(&state, pile.cards.iter());
-                let strength = GameState::evaluate_house_pile_value(pile) as f32;
-                if value < strength {
-                    Some(idx)
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+        // Do attacks, unless the turn was taken over after the attack phase:
+        let can_attack = state.round_state.turn_state == TurnState::Attack
+            && self.difficulty != Difficulty::Easy;
+        let attack_piles = if can_attack {
+            state.players[self.player_id]
+                .house_piles()
+                .into_iter()
+                .filter_map(|(idx, pile)| {
+                    let value = self.evaluate_house_pile(&state, pile.cards.iter());
+                    let strength = GameState::evaluate_house_pile_value(pile) as f32;
+                    if value < strength {
+                        Some(idx)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
         for idx in attack_piles {
-            if let Some((suit, _)) = state
+            let strength = state.players[self.player_id]
+                .get_house_pile(idx)
+                .as_ref()
+                .map_or(0, GameState::evaluate_house_pile_value);
+            if let Some((suit, target_idx, value)) = state
                 .players
-                .iter_mut()
+                .iter()
                 .enumerate()
                 .filter_map(|(i, p)| {
                     if i == self.player_id {
                         None
                     } else {
-                        let suit = p.suit;
-                        p.first_house_pile().map(|sp| (suit, sp))
+                        p.first_house_pile_index().map(|idx| (p.suit, idx, p))
                     }
                 })
-                .map(|(s, sp)| {
+                .map(|(s, idx, p)| {
+                    let sp = p.get_house_pile(idx);
                     (
                         s,
+                        idx,
                         GameState::evaluate_house_pile_value(sp.as_ref().unwrap()),
                     )
                 })
-                .min_by(|(_, val_a), (_, val_b)| val_a.cmp(val_b))
+                .min_by(|(_, _, val_a), (_, _, val_b)| val_a.cmp(val_b))
+                .filter(|(_, _, value)| self.difficulty != Difficulty::Hard || *value < strength)
             {
                 let attack = PlayerAction::Attack {
                     house_pile: idx,
@@ -133,7 +181,24 @@ impl AIPlayer {
                 state
                     .perform_player_action(self.player_id, attack.clone())
                     .unwrap();
-                actions.push(attack)
+                let explanation = if strength > value {
+                    format!(
+                        "attack {}' pile {}: your {} beats their {}",
+                        suit_name(suit),
+                        target_idx.to_string(),
+                        strength,
+                        value
+                    )
+                } else {
+                    format!(
+                        "attack {}' pile {}: your {} loses to their {}, but your pile is of more use to the others",
+                        suit_name(suit),
+                        target_idx.to_string(),
+                        strength,
+                        value
+                    )
+                };
+                actions.push((attack, explanation))
             }
         }
 
@@ -147,22 +212,74 @@ impl AIPlayer {
             })
             .next()
         {
+            let explanation = self.explain_put_down(&state, card, pile);
             let action = PlayerAction::AddCardToPile { pile, card };
-            actions.push(action.clone());
+            actions.push((action.clone(), explanation));
             state.perform_player_action(self.player_id, action).unwrap();
         }
 
         //TODO: Reorder cards:
 
-        actions.push(PlayerAction::DiscardHand);
+        actions.push((
+            PlayerAction::DiscardHand,
+            "discard your hand: none of your cards fit on your piles".to_owned(),
+        ));
 
         actions
     }
+
+    fn explain_put_down(&self, state: &GameState, card: Card, pile: PlayerPile) -> String {
+        let idx = match pile {
+            PlayerPile::KingPile => {
+                return format!(
+                    "put your {:?} on your king pile: it counts towards winning",
+                    card
+                )
+            }
+            PlayerPile::HousePile(idx) => idx,
+        };
+        let house_pile = match state.players[self.player_id].get_house_pile(idx) {
+            Some(house_pile) => house_pile,
+            None => {
+                let reason = match card.rank {
+                    Rank::Queen => "pairs of ranks go on it, every card counts twice",
+                    Rank::Jack => "cards of its suit go on it",
+                    _ => "straights of different ranks go on it",
+                };
+                return format!(
+                    "start house pile {} with your {:?}: {}",
+                    idx.to_string(),
+                    card,
+                    reason
+                );
+            }
+        };
+        let reason = match house_pile.special_card.rank {
+            Rank::Queen if house_pile.cards.contains_rank(card.rank) => {
+                "it completes a pair on that queen pile"
+            }
+            Rank::Queen => "it starts a new pair on that queen pile",
+            Rank::Jack => "it matches the suit of that jack pile",
+            _ => "it extends the straight on that ace pile",
+        };
+        format!(
+            "put your {:?} on house pile {}: {}",
+            card,
+            idx.to_string(),
+            reason
+        )
+    }
+}
+
+/// Plural name of the player playing `suit`, like "Spades".
+fn suit_name(suit: Suit) -> String {
+    format!("{:?}s", suit)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::cards::SpecialPile;
 
     #[test]
     fn it_plays_with_ai() {
@@ -190,4 +307,66 @@ mod test {
             //panic!("\n{:#?}\n", state);
         }
     }
+
+    #[test]
+    fn it_plays_on_every_difficulty() {
+        let difficulties = [
+            Difficulty::Easy,
+            Difficulty::Normal,
+            Difficulty::Hard,
+            Difficulty::Normal,
+        ];
+        for _ in 0..20 {
+            let mut state = GameState::initial();
+            let mut ais = (0..4)
+                .map(|player| AIPlayer::with_difficulty(player, difficulties[player]))
+                .collect::<Vec<_>>();
+
+            for _ in 0..50 {
+                for (player, ai) in ais.iter_mut().enumerate() {
+                    for action in ai.play_turn(state.clone()) {
+                        if let PlayerAction::Attack { .. } = action {
+                            assert_ne!(difficulties[player], Difficulty::Easy);
+                        }
+                        state.perform_player_action(player, action).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_hints_its_first_move() {
+        let state = GameState::initial();
+        let mut ai = AIPlayer::with_difficulty(0, Difficulty::Hard);
+        let (action, explanation) = ai.hint(state.clone());
+        let actions = ai.play_turn(state);
+        assert_eq!(action.to_string(), actions[0].to_string());
+        assert!(!explanation.is_empty());
+    }
+
+    #[test]
+    fn it_explains_attacks_on_stronger_piles() {
+        let mut state = GameState::initial();
+        let mut queens = SpecialPile::new(Card::new(Suit::Heart, Rank::Queen));
+        queens
+            .cards
+            .add(Card::new(state.players[0].suit, Rank::Five));
+        state.players[0].house_pile_1 = Some(queens);
+        let mut target = SpecialPile::new(Card::new(Suit::Club, Rank::Queen));
+        target.cards.add(Card::new(Suit::Club, Rank::Six));
+        target.cards.add(Card::new(Suit::Spade, Rank::Six));
+        state.players[1].house_pile_2 = Some(target);
+
+        let (action, explanation) = AIPlayer::new(0).hint(state.clone());
+        let suit = state.players[1].suit;
+        assert_eq!(action.to_string(), format!("atck:1{}", suit.to_string()));
+        assert_eq!(
+            explanation,
+            format!(
+                "attack {}' pile 2: your 2 loses to their 4, but your pile is of more use to the others",
+                suit_name(suit)
+            )
+        );
+    }
 }
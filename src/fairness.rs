@@ -0,0 +1,95 @@
+//! Provably fair shuffling. The server commits to its seed before anyone
+//! joins by publishing its hash, players may mix in seeds of their own, and
+//! everything is revealed once the game is over so the deal and every
+//! reshuffle can be checked.
+
+use sha2::{Digest, Sha256};
+
+use crate::game::GameState;
+
+/// Most bytes a player may contribute to the seed.
+pub const MAX_CONTRIBUTION_LEN: usize = 64;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The commitment published for `server_seed`, a hex SHA-256 hash.
+pub fn commit(server_seed: &[u8; 32]) -> String {
+    to_hex(&Sha256::digest(server_seed))
+}
+
+/// The seed the game is dealt with, the hash of the server seed and the
+/// contributions in seat order. Contributions are length prefixed, so no two
+/// different sets of contributions hash alike.
+pub fn derive_seed(server_seed: &[u8; 32], contributions: &[Option<String>]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(server_seed);
+    for contribution in contributions {
+        let bytes = contribution.as_deref().unwrap_or("").as_bytes();
+        hasher.input((bytes.len() as u64).to_be_bytes());
+        hasher.input(bytes);
+    }
+    let mut seed = [0; 32];
+    seed.copy_from_slice(&hasher.result());
+    seed
+}
+
+/// Checks that `state` is what dealing with the committed seed and replaying
+/// its actions leads to, which re-derives every shuffle of the game.
+pub fn verify(
+    commitment: &str,
+    server_seed: &[u8; 32],
+    contributions: &[Option<String>],
+    state: &GameState,
+) -> bool {
+    if commit(server_seed) != commitment {
+        return false;
+    }
+    let seed = derive_seed(server_seed, contributions);
+    if seed != state.seed() {
+        return false;
+    }
+    match GameState::replay(state.first_player, seed, &state.log) {
+        Ok(replayed) => {
+            replayed.to_string() == state.to_string()
+                && replayed.hidden_to_string() == state.hidden_to_string()
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ai::AIPlayer;
+
+    #[test]
+    fn it_verifies_played_games() {
+        let server_seed = [3; 32];
+        let commitment = commit(&server_seed);
+        let contributions = [Some("alice".to_owned()), None, None, Some("bob".to_owned())];
+        let seed = derive_seed(&server_seed, &contributions);
+        assert_ne!(seed, derive_seed(&server_seed, &[None, None, None, None]));
+
+        let mut state = GameState::with_seed(0, seed);
+        for _ in 0..50 {
+            for player in 0..4 {
+                for action in AIPlayer::new(player).play_turn(state.clone()) {
+                    state.perform_player_action(player, action).unwrap();
+                }
+            }
+        }
+        assert!(verify(&commitment, &server_seed, &contributions, &state));
+        assert!(!verify(&commitment, &[4; 32], &contributions, &state));
+        assert!(!verify(
+            &commitment,
+            &server_seed,
+            &[None, None, None, None],
+            &state
+        ));
+
+        state.log.pop();
+        assert!(!verify(&commitment, &server_seed, &contributions, &state));
+    }
+}
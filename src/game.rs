@@ -2,7 +2,7 @@ use core::str::FromStr;
 use std::slice::Iter;
 
 use rand::prelude::*;
-use rand::rngs::StdRng;
+use rand_chacha::ChaCha20Rng;
 
 use crate::cards::{Card, Pile, Rank, SpecialPile, Suit};
 
@@ -105,6 +105,7 @@ pub enum PlayerAction {
     },
     SwapHousePile(HousePile, HousePile),
     DiscardHand,
+    Resign,
 }
 
 impl FromStr for PlayerAction {
@@ -141,6 +142,7 @@ impl FromStr for PlayerAction {
                 PlayerAction::SwapHousePile(a, b)
             }
             ("dscd:", "") => PlayerAction::DiscardHand,
+            ("resg:", "") => PlayerAction::Resign,
             _ => Err(())?,
         })
     }
@@ -162,13 +164,22 @@ impl ToString for PlayerAction {
             }
             PlayerAction::SwapHousePile(a, b) => format!("swap:{}{}", a.to_string(), b.to_string()),
             PlayerAction::DiscardHand => format!("dscd:"),
+            PlayerAction::Resign => "resg:".to_string(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct GameState {
-    rng: StdRng,
+    /// Seed of `rng`, kept secret until the game is over.
+    seed: [u8; 32],
+    /// A named generator rather than `StdRng`, whose algorithm may change
+    /// between versions, so anyone can redo the shuffles from the seed.
+    rng: ChaCha20Rng,
+    pub first_player: usize,
+    /// Every action performed so far, the game can be replayed from them and
+    /// the seed.
+    pub log: Vec<(usize, PlayerAction)>,
     pub round_state: RoundState,
     pub discard_pile: Pile,
     pub stock_pile: Pile,
@@ -177,17 +188,30 @@ pub struct GameState {
 
 impl GameState {
     pub fn initial() -> GameState {
-        let mut rng = StdRng::from_entropy();
+        GameState::starting_with(0)
+    }
+
+    /// Deals a new game in which `first_player` takes the first turn.
+    pub fn starting_with(first_player: usize) -> GameState {
+        GameState::with_seed(first_player, rand::random())
+    }
+
+    /// Deals the game `seed` stands for, every shuffle follows from it.
+    pub fn with_seed(first_player: usize, seed: [u8; 32]) -> GameState {
+        let mut rng = ChaCha20Rng::from_seed(seed);
         let stock_pile = Pile::new()
             .add_without_kings()
             .add_blank_without_kings(4)
             .shuffled(&mut rng);
         let mut game = GameState {
             round_state: RoundState {
-                player: 3,
+                player: (first_player + 3) % 4,
                 turn_state: TurnState::Attack,
             },
+            seed,
             rng,
+            first_player,
+            log: Vec::new(),
             discard_pile: Pile::new(),
             stock_pile,
             players: vec![
@@ -240,12 +264,61 @@ impl GameState {
         self.players.iter_mut().find(|ps| ps.suit == player)
     }
 
+    /// Deals the game again from `seed` and performs the actions of `log`,
+    /// this has to end up in the same state as the original game.
+    pub fn replay(
+        first_player: usize,
+        seed: [u8; 32],
+        log: &[(usize, PlayerAction)],
+    ) -> Result<GameState, &'static str> {
+        let mut state = GameState::with_seed(first_player, seed);
+        for (player, action) in log {
+            state.perform_player_action(*player, action.clone())?;
+        }
+        Ok(state)
+    }
+
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    /// A copy of the game without its action log, which keeps growing
+    /// while the game runs.
+    pub fn clone_without_log(&self) -> GameState {
+        GameState {
+            seed: self.seed,
+            rng: self.rng.clone(),
+            first_player: self.first_player,
+            log: Vec::new(),
+            round_state: self.round_state.clone(),
+            discard_pile: self.discard_pile.clone(),
+            stock_pile: self.stock_pile.clone(),
+            players: self.players.clone(),
+        }
+    }
+
     // Returns Some(player) when it is the next players turn. Returns none otherwise.
     pub fn perform_player_action(
         &mut self,
         player: usize,
         action: PlayerAction,
     ) -> Result<PlayerActionResult, &'static str> {
+        let result = self.apply_player_action(player, action.clone())?;
+        self.log.push((player, action));
+        Ok(result)
+    }
+
+    fn apply_player_action(
+        &mut self,
+        player: usize,
+        action: PlayerAction,
+    ) -> Result<PlayerActionResult, &'static str> {
+        if self.players[player].forfeited {
+            Err("you are no longer in the game")?;
+        }
+        if let PlayerAction::Resign = action {
+            return Ok(self.forfeit(player));
+        }
         if player != self.round_state.player {
             Err("not your turn")?;
         }
@@ -320,6 +393,7 @@ impl GameState {
                 self.round_state.turn_state = TurnState::Organize;
                 self.players[player as usize].swap_house_piles(a, b);
             }
+            (_, PlayerAction::Resign) => unreachable!(),
             (_, PlayerAction::DiscardHand) => {
                 let player = &mut self.players[player as usize];
                 let hand = std::mem::take(&mut player.hand);
@@ -336,6 +410,116 @@ impl GameState {
         }
     }
 
+    /// Tells what attacking `target_player` with `house_pile` would do,
+    /// without changing anything.
+    pub fn preview_attack(
+        &self,
+        player: usize,
+        house_pile: HousePile,
+        target_player: Suit,
+    ) -> Result<AttackPreview, &'static str> {
+        if self.players[player].forfeited {
+            Err("you are no longer in the game")?;
+        }
+        if player != self.round_state.player {
+            Err("not your turn")?;
+        }
+        if self.round_state.turn_state != TurnState::Attack {
+            Err("the action is not possible at this point in the turn")?;
+        }
+        let attack_pile = self.players[player]
+            .get_house_pile(house_pile)
+            .as_ref()
+            .ok_or("chose non existent house pile to attack")?;
+        let target = self
+            .players
+            .iter()
+            .position(|p| p.suit == target_player)
+            .ok_or("attack target player does not exist")?;
+        // The attacking pile is taken before the defending one is picked.
+        let defending_idx = if target == player {
+            let mut player_state = self.players[player].clone();
+            player_state.get_mut_house_pile(house_pile).take();
+            player_state.first_house_pile_index()
+        } else {
+            self.players[target].first_house_pile_index()
+        };
+        let defending_pile = defending_idx.map(|idx| {
+            let pile = self.players[target].get_house_pile(idx).as_ref().unwrap();
+            (idx, pile)
+        });
+
+        let attack_value = GameState::evaluate_house_pile_value(attack_pile);
+        let preview = match defending_pile {
+            Some((idx, pile)) => {
+                let defense_value = GameState::evaluate_house_pile_value(pile);
+                AttackPreview {
+                    defending_pile: Some(idx),
+                    attack_value,
+                    defense_value: Some(defense_value),
+                    outcome: if attack_value > defense_value {
+                        AttackOutcome::Won(pile.cards.clone())
+                    } else {
+                        AttackOutcome::Lost(attack_pile.cards.clone())
+                    },
+                }
+            }
+            None => AttackPreview {
+                defending_pile: None,
+                attack_value,
+                defense_value: None,
+                outcome: AttackOutcome::Unopposed,
+            },
+        };
+        Ok(preview)
+    }
+
+    /// Takes `player` out of the game, all their cards except the king go to
+    /// the discard pile.
+    pub fn forfeit(&mut self, player: usize) -> PlayerActionResult {
+        let player_state = &mut self.players[player];
+        if player_state.forfeited {
+            return PlayerActionResult::Nominal;
+        }
+        player_state.forfeited = true;
+        let mut cards = player_state.hand.take();
+        cards.add_pile(player_state.king_pile.cards.take());
+        for pile in HousePile::iter() {
+            if let Some(pile) = player_state.get_mut_house_pile(*pile).take() {
+                cards.add(pile.special_card);
+                cards.add_pile(pile.cards);
+            }
+        }
+        self.discard_pile.add_pile(cards);
+
+        let mut remaining = (0..self.players.len()).filter(|p| !self.players[*p].forfeited);
+        if let (Some(winner), None) = (remaining.next(), remaining.next()) {
+            PlayerActionResult::GameWon(winner)
+        } else if self.round_state.player == player {
+            self.next_player();
+            PlayerActionResult::NextPlayer(self.round_state.player)
+        } else {
+            PlayerActionResult::Nominal
+        }
+    }
+
+    /// Orders the players from best to worst by the cards on their king
+    /// piles, players that forfeited come last.
+    pub fn ranking(&self) -> Vec<usize> {
+        let mut ranking: Vec<usize> = (0..self.players.len()).collect();
+        ranking.sort_by_key(|p| self.standing(*p));
+        ranking
+    }
+
+    /// Sort key of `player` in the ranking, equal for tied players.
+    pub fn standing(&self, player: usize) -> (bool, std::cmp::Reverse<usize>) {
+        let player = &self.players[player];
+        (
+            player.forfeited,
+            std::cmp::Reverse(player.king_pile.cards.count()),
+        )
+    }
+
     fn next_player(&mut self) {
         if self.stock_pile.count() < 5 {
             let discard = self.discard_pile.take().shuffled(&mut self.rng);
@@ -344,14 +528,51 @@ impl GameState {
         let hand = self.stock_pile.take_up_to_n(5);
         self.round_state.turn_state = TurnState::Attack;
 
-        self.round_state.player += 1;
-        self.round_state.player %= self.players.len();
+        for _ in 0..self.players.len() {
+            self.round_state.player += 1;
+            self.round_state.player %= self.players.len();
+            if !self.players[self.round_state.player].forfeited {
+                break;
+            }
+        }
 
         self.players[self.round_state.player as usize]
             .hand
             .add_pile(hand);
     }
 
+    /// What the players can't see: every hand, the stock in the order it is
+    /// drawn and the discard pile.
+    pub fn hidden_to_string(&self) -> String {
+        let mut s = "hands:".to_owned();
+        for player in &self.players {
+            s += &player.hand.to_string();
+        }
+        s += &format!("\nstock:{:02}", self.stock_pile.count());
+        for c in self.stock_pile.iter().rev() {
+            s += &c.to_string();
+        }
+        s += &format!("\ndscrd:{}", self.discard_pile.to_string());
+        s
+    }
+
+    /// Everything there is to know about the game, for when it is over.
+    pub fn reveal_to_string(&self) -> String {
+        let seed: String = self.seed.iter().map(|b| format!("{:02x}", b)).collect();
+        let actions: Vec<String> = self
+            .log
+            .iter()
+            .map(|(player, action)| format!("{}{}", player, action.to_string()))
+            .collect();
+        format!(
+            "{}\nrseed:{}\nfirst:{}\nactns:{}",
+            self.hidden_to_string(),
+            seed,
+            self.first_player,
+            actions.join(",")
+        )
+    }
+
     pub fn hand_to_string(&self) -> String {
         let player = &self.players[self.round_state.player];
         let hand = &player.hand;
@@ -389,6 +610,8 @@ pub struct PlayerState {
     pub house_pile_2: Option<SpecialPile>,
     pub house_pile_3: Option<SpecialPile>,
     pub hand: Pile,
+    /// Forfeited players are skipped and can't win anymore.
+    pub forfeited: bool,
 }
 
 impl PlayerState {
@@ -400,6 +623,7 @@ impl PlayerState {
             house_pile_2: None,
             house_pile_3: None,
             hand: Pile::new(),
+            forfeited: false,
         }
     }
 
@@ -444,15 +668,15 @@ impl PlayerState {
     }
 
     pub fn first_house_pile(&mut self) -> Option<&mut Option<SpecialPile>> {
-        if self.house_pile_1.is_some() {
-            Some(&mut self.house_pile_1)
-        } else if self.house_pile_2.is_some() {
-            Some(&mut self.house_pile_2)
-        } else if self.house_pile_3.is_some() {
-            Some(&mut self.house_pile_3)
-        } else {
-            None
-        }
+        let idx = self.first_house_pile_index()?;
+        Some(self.get_mut_house_pile(idx))
+    }
+
+    /// The house pile attacks on this player go against.
+    pub fn first_house_pile_index(&self) -> Option<HousePile> {
+        HousePile::iter()
+            .copied()
+            .find(|idx| self.get_house_pile(*idx).is_some())
     }
 
     pub fn house_piles(&self) -> Vec<(HousePile, &SpecialPile)> {
@@ -508,9 +732,98 @@ impl ToString for PlayerState {
     }
 }
 
+/// What an attack would do, see `GameState::preview_attack`.
+#[derive(Clone, Debug)]
+pub struct AttackPreview {
+    /// The pile of the target that defends, `None` if they have none.
+    pub defending_pile: Option<HousePile>,
+    pub attack_value: u32,
+    pub defense_value: Option<u32>,
+    pub outcome: AttackOutcome,
+}
+
+#[derive(Clone, Debug)]
+pub enum AttackOutcome {
+    /// The defending pile is beaten, its cards go to the attacker's hand and
+    /// both special cards and the attacking cards are discarded.
+    Won(Pile),
+    /// The defending pile holds, the attacking cards go to the defender's
+    /// hand and its special card is discarded.
+    Lost(Pile),
+    /// The target has no house pile, the attacking pile is lost.
+    Unopposed,
+}
+
 #[derive(Clone, Debug)]
 pub enum PlayerActionResult {
     Nominal,
     NextPlayer(usize),
     GameWon(usize),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_skips_forfeited_players() {
+        let mut state = GameState::initial();
+        assert_eq!(state.round_state.player, 0);
+
+        assert!(matches!(state.forfeit(1), PlayerActionResult::Nominal));
+        assert!(state.players[1].hand.is_empty());
+        state
+            .perform_player_action(0, PlayerAction::DiscardHand)
+            .unwrap();
+        assert_eq!(state.round_state.player, 2);
+
+        assert!(matches!(
+            state.forfeit(2),
+            PlayerActionResult::NextPlayer(3)
+        ));
+        assert!(matches!(state.forfeit(0), PlayerActionResult::GameWon(3)));
+        assert_eq!(
+            state.discard_pile.count() + state.stock_pile.count() + state.players[3].hand.count(),
+            96
+        );
+    }
+
+    #[test]
+    fn it_deals_by_seed() {
+        let a = GameState::with_seed(0, [7; 32]);
+        let b = GameState::with_seed(0, [7; 32]);
+        assert_eq!(a.hidden_to_string(), b.hidden_to_string());
+        assert!(a.reveal_to_string().contains(&"07".repeat(32)));
+    }
+
+    #[test]
+    fn it_previews_attacks() {
+        let mut state = GameState::initial();
+        let mut queens = SpecialPile::new(Card::new(Suit::Heart, Rank::Queen));
+        queens.cards.add(Card::new(Suit::Club, Rank::Five));
+        queens.cards.add(Card::new(Suit::Spade, Rank::Five));
+        state.players[0].house_pile_1 = Some(queens);
+        let mut jacks = SpecialPile::new(Card::new(Suit::Club, Rank::Jack));
+        jacks.cards.add(Card::new(Suit::Club, Rank::Three));
+        state.players[1].house_pile_2 = Some(jacks);
+        let target = state.players[1].suit;
+
+        let preview = state.preview_attack(0, HousePile::One, target).unwrap();
+        assert_eq!(preview.defending_pile, Some(HousePile::Two));
+        assert_eq!((preview.attack_value, preview.defense_value), (4, Some(1)));
+        assert!(matches!(&preview.outcome, AttackOutcome::Won(cards) if cards.count() == 1));
+        assert!(state.preview_attack(0, HousePile::Two, target).is_err());
+        assert!(state
+            .preview_attack(1, HousePile::Two, Suit::Heart)
+            .is_err());
+
+        let hand = state.players[0].hand.count();
+        let attack = PlayerAction::Attack {
+            house_pile: HousePile::One,
+            target_player: target,
+        };
+        state.perform_player_action(0, attack).unwrap();
+        assert_eq!(state.players[0].hand.count(), hand + 1);
+        assert!(state.players[1].house_pile_2.is_none());
+    }
+}
@@ -1,14 +1,25 @@
-use server::Server;
+#![recursion_limit = "256"]
 
+use server::{Server, ServerConfig};
+
+pub mod accounts;
 pub mod ai;
 pub mod cards;
+pub mod fairness;
 pub mod game;
+pub mod ratings;
 pub mod server;
+pub mod store;
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
-    let server = Server::new();
+    let server = Server::with_config(ServerConfig {
+        accounts_path: Some("accounts.json".into()),
+        ratings_path: Some("ratings.json".into()),
+        observer_secret: std::env::var("OBSERVER_SECRET").ok(),
+        ..Default::default()
+    });
     server.add_test_game(0);
     server.serve(([127, 0, 0, 1], 3030)).await;
 }
@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ai::Difficulty;
+use crate::store;
+
+/// Rating everyone starts with.
+const INITIAL_RATING: f64 = 1500.0;
+/// Most a player can win or lose in one game.
+const K_FACTOR: f64 = 32.0;
+
+/// Someone who can be rated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Entity {
+    /// An account id.
+    Player(u64),
+    AI(Difficulty),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rating {
+    pub entity: Entity,
+    pub rating: f64,
+    pub games: u32,
+}
+
+/// Elo ratings of all players and AI strategies, saved to a JSON file after
+/// every game if the server has one.
+#[derive(Debug, Default)]
+pub struct Ratings {
+    path: Option<PathBuf>,
+    ratings: Mutex<Vec<Rating>>,
+}
+
+impl Ratings {
+    /// Loads the ratings from `path`, which is created after the first rated
+    /// game if it doesn't exist yet.
+    pub fn open(path: PathBuf) -> io::Result<Ratings> {
+        let ratings = store::load(&path)?;
+        Ok(Ratings {
+            path: Some(path),
+            ratings: Mutex::new(ratings),
+        })
+    }
+
+    pub fn get(&self, entity: Entity) -> f64 {
+        rating_of(&self.ratings.lock().unwrap(), entity)
+    }
+
+    /// Updates the ratings after a game, `ranking` has the place every
+    /// player finished on, tied players share one. Every player is scored
+    /// against every other one as if they played a game of two, the changes
+    /// are scaled so a game is worth as much as a single one of two players.
+    pub fn update(&self, ranking: &[(Entity, usize)]) -> io::Result<()> {
+        if ranking.len() < 2 {
+            return Ok(());
+        }
+        let mut ratings = self.ratings.lock().unwrap();
+        let before: Vec<f64> = ranking
+            .iter()
+            .map(|(e, _)| rating_of(&ratings, *e))
+            .collect();
+        let scale = K_FACTOR / (ranking.len() - 1) as f64;
+
+        for (i, (entity, place)) in ranking.iter().enumerate() {
+            let mut change = 0.0;
+            for (j, (other, other_place)) in ranking.iter().enumerate() {
+                if entity == other {
+                    continue;
+                }
+                let expected = 1.0 / (1.0 + 10f64.powf((before[j] - before[i]) / 400.0));
+                let score = match place.cmp(other_place) {
+                    Ordering::Less => 1.0,
+                    Ordering::Equal => 0.5,
+                    Ordering::Greater => 0.0,
+                };
+                change += scale * (score - expected);
+            }
+            let index = match ratings.iter().position(|r| r.entity == *entity) {
+                Some(index) => index,
+                None => {
+                    ratings.push(Rating {
+                        entity: *entity,
+                        rating: INITIAL_RATING,
+                        games: 0,
+                    });
+                    ratings.len() - 1
+                }
+            };
+            ratings[index].rating += change;
+            ratings[index].games += 1;
+        }
+
+        match &self.path {
+            Some(path) => store::save(path, &ratings),
+            None => Ok(()),
+        }
+    }
+
+    /// All ratings, best first.
+    pub fn leaderboard(&self) -> Vec<Rating> {
+        let mut ratings = self.ratings.lock().unwrap().clone();
+        ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
+        ratings
+    }
+}
+
+fn rating_of(ratings: &[Rating], entity: Entity) -> f64 {
+    ratings
+        .iter()
+        .find(|r| r.entity == entity)
+        .map_or(INITIAL_RATING, |r| r.rating)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_rates_by_ranking() {
+        let ratings = Ratings::default();
+        let ranking = [
+            (Entity::Player(2), 0),
+            (Entity::AI(Difficulty::Hard), 1),
+            (Entity::Player(1), 2),
+            (Entity::AI(Difficulty::Easy), 3),
+        ];
+        ratings.update(&ranking).unwrap();
+
+        let after: Vec<f64> = ranking.iter().map(|(e, _)| ratings.get(*e)).collect();
+        assert!(after.windows(2).all(|w| w[0] > w[1]));
+        assert!((after.iter().sum::<f64>() - 4.0 * INITIAL_RATING).abs() < 1e-6);
+        assert_eq!(ratings.leaderboard()[0].entity, Entity::Player(2));
+
+        let tied = Ratings::default();
+        tied.update(&[(Entity::Player(1), 0), (Entity::Player(2), 0)])
+            .unwrap();
+        assert_eq!(tied.get(Entity::Player(1)), INITIAL_RATING);
+    }
+}
@@ -1,44 +1,315 @@
 use serde::Deserializer;
-use std::collections::{hash_map::Entry, HashMap};
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::{
     fmt::Display,
     str::FromStr,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use futures::{stream, StreamExt};
-use serde::{de, Deserialize};
-use tokio::{sync::broadcast, time::interval};
-use warp::{path, path::param, query, reject, sse, Filter, Rejection, Reply};
+use futures::{future, stream, StreamExt};
 use log::warn;
+use serde::{de, Deserialize, Serialize};
+use tokio::{
+    sync::{
+        broadcast::{self, RecvError},
+        mpsc, oneshot,
+    },
+    time::{self, delay_for, delay_until, interval},
+};
+use warp::{path, path::param, query, reject, sse, Filter, Rejection, Reply};
+
+use crate::accounts::{AccountError, Accounts, Identity};
+use crate::ai::{AIPlayer, Difficulty};
+use crate::cards::Suit;
+use crate::fairness;
+use crate::game::{AttackOutcome, GameState, HousePile, PlayerAction, PlayerActionResult};
+use crate::ratings::{Entity, Ratings};
 
-use crate::ai::AIPlayer;
-use crate::game::{GameState, PlayerAction, PlayerActionResult};
+/// Amount of past events every game keeps around for clients resuming their
+/// event stream with a `Last-Event-ID`.
+const EVENT_LOG_SIZE: usize = 64;
+
+/// Longest chat message of a player, in characters.
+const MAX_CHAT_LEN: usize = 200;
+
+/// Longest chat message of a spectator, in characters. Anyone may watch, so
+/// they get less room than the players.
+const MAX_SPECTATOR_CHAT_LEN: usize = 100;
 
 #[derive(Debug)]
 struct Game {
     creation_time: Instant,
-    notify_change: broadcast::Sender<GameEvent>,
+    config: Arc<ServerConfig>,
+    ratings: Arc<Ratings>,
+    notify_change: broadcast::Sender<(u64, GameEvent)>,
     inner: Mutex<GameInner>,
 }
 
+/// What happens when a player runs out of time for their turn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+enum TimeoutPolicy {
+    #[serde(rename = "dscd")]
+    Discard,
+    #[serde(rename = "ai")]
+    AIMove,
+}
+
+/// What happens when a player's clock runs out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+enum FlagPenalty {
+    #[serde(rename = "dscd")]
+    Discard,
+    #[serde(rename = "resg")]
+    Forfeit,
+}
+
+/// What a player votes for to end a game early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+enum Vote {
+    /// Ends the game without a result.
+    #[serde(rename = "abrt")]
+    Abort,
+    /// Ends the game, ranking the players by their king piles.
+    #[serde(rename = "end")]
+    End,
+}
+
+/// Quick reactions players can send instead of typing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Emote {
+    Hello,
+    #[serde(rename = "gg")]
+    GoodGame,
+    Thanks,
+    Oops,
+    Wow,
+}
+
+impl Emote {
+    fn as_str(self) -> &'static str {
+        match self {
+            Emote::Hello => "hello",
+            Emote::GoodGame => "gg",
+            Emote::Thanks => "thanks",
+            Emote::Oops => "oops",
+            Emote::Wow => "wow",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GameResult {
+    Won(usize),
+    Ended,
+    Aborted,
+}
+
+/// How a game ended.
+#[derive(Clone, Debug)]
+struct GameOutcome {
+    result: GameResult,
+    /// Seats from first to last place, empty for aborted games.
+    ranking: Vec<usize>,
+    finished: SystemTime,
+    /// Every hand, the stock, the discard pile and the seeds at the end.
+    reveal: String,
+}
+
+impl GameOutcome {
+    fn new(inner: &GameInner, result: GameResult) -> GameOutcome {
+        let state = &inner.state;
+        let ranking = match result {
+            GameResult::Won(winner) => {
+                let mut ranking = state.ranking();
+                ranking.retain(|p| *p != winner);
+                ranking.insert(0, winner);
+                ranking
+            }
+            GameResult::Ended => state.ranking(),
+            GameResult::Aborted => vec![],
+        };
+        GameOutcome {
+            result,
+            ranking,
+            finished: SystemTime::now(),
+            reveal: format!(
+                "{}\nsseed:{}\nseeds:{}",
+                state.reveal_to_string(),
+                fairness::to_hex(&inner.server_seed),
+                seeds_to_string(&inner.seed_contributions)
+            ),
+        }
+    }
+}
+
+/// Renders the seeds contributed per seat in hex, so they can't contain the
+/// separator.
+fn seeds_to_string(seeds: &[Option<String>]) -> String {
+    let seeds: Vec<_> = seeds
+        .iter()
+        .map(|s| fairness::to_hex(s.as_deref().unwrap_or("").as_bytes()))
+        .collect();
+    seeds.join(",")
+}
+
+/// Renders the result and the ranking, like `end:2013`.
+fn outcome_to_string(outcome: &GameOutcome) -> String {
+    let result = match outcome.result {
+        GameResult::Won(_) => "won",
+        GameResult::Ended => "end",
+        GameResult::Aborted => "abrt",
+    };
+    let ranking: String = outcome.ranking.iter().map(|p| p.to_string()).collect();
+    format!("{}:{}", result, ranking)
+}
+
+/// Rules a game was created with.
+#[derive(Clone, Debug)]
+struct GameSettings {
+    turn_time: Option<Duration>,
+    on_timeout: TimeoutPolicy,
+    /// Total thinking time of every player, like a chess clock.
+    time_bank: Option<Duration>,
+    /// Time added to a player's clock after every turn.
+    increment: Duration,
+    on_flag: FlagPenalty,
+    /// Private games are hidden from the lobby and need an invite or the
+    /// password to join.
+    private: bool,
+    password: Option<String>,
+    /// How well the AI players in the seats play.
+    ai_difficulty: Difficulty,
+    /// Rated games change the ratings of the players with an account and of
+    /// the AI players. Their players can only agree to abort them.
+    rated: bool,
+    /// How far the god view lags behind the game, without a delay it only
+    /// opens once the game is over.
+    god_view_delay: Option<Duration>,
+    /// Starts the game as soon as every seat is taken and ready, instead of
+    /// waiting for the host.
+    auto_start: bool,
+}
+
+impl GameSettings {
+    /// A short description of the rules, like `turn60s-clock600s+5s`.
+    pub fn variant(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(turn_time) = self.turn_time {
+            parts.push(format!("turn{}s", turn_time.as_secs()));
+        }
+        if let Some(time_bank) = self.time_bank {
+            parts.push(format!(
+                "clock{}s+{}s",
+                time_bank.as_secs(),
+                self.increment.as_secs()
+            ));
+        }
+        match self.ai_difficulty {
+            Difficulty::Easy => parts.push("easyai".to_owned()),
+            Difficulty::Normal => {}
+            Difficulty::Hard => parts.push("hardai".to_owned()),
+        }
+        if self.rated {
+            parts.push("rated".to_owned());
+        }
+        if parts.is_empty() {
+            "standard".to_owned()
+        } else {
+            parts.join("-")
+        }
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> GameSettings {
+        GameSettings {
+            turn_time: None,
+            on_timeout: TimeoutPolicy::Discard,
+            time_bank: None,
+            increment: Duration::from_secs(0),
+            on_flag: FlagPenalty::Discard,
+            private: false,
+            password: None,
+            ai_difficulty: Difficulty::Normal,
+            rated: false,
+            god_view_delay: None,
+            auto_start: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct GameInner {
     state: GameState,
-    players: Vec<Player>,
+    settings: GameSettings,
+    seats: [Option<Seat>; 4],
+    /// The player managing the game, its creator once they joined. Games
+    /// created without an account are managed by the first human player to
+    /// join.
+    host: Option<usize>,
+    /// Account the game was created with.
+    creator: Option<u64>,
+    invites: HashSet<String>,
     is_started: bool,
+    /// Counts the turns, so timers can tell whether their turn is still going.
+    turn: u64,
+    turn_deadline: Option<Instant>,
+    /// Time left on every player's clock, when it was last stopped.
+    clocks: [Duration; 4],
+    /// When the clock of the current player was started, `None` if it isn't
+    /// running.
+    clock_started: Option<Instant>,
+    last_event_id: u64,
+    event_log: VecDeque<(u64, GameEvent)>,
+    /// Open event streams per seat.
+    connections: [usize; 4],
+    disconnected_since: [Option<Instant>; 4],
+    /// AI players standing in for disconnected human players.
+    stand_ins: [Option<AIPlayer>; 4],
+    /// Votes to end the game early, per seat.
+    votes: [Option<Vote>; 4],
+    /// Accounts that left the running game, rated below everyone else.
+    left_accounts: Vec<u64>,
+    /// Set once the game is over, kept as its record.
+    outcome: Option<GameOutcome>,
+    /// The seat that took the first turn.
+    first_player: usize,
+    /// Id of the follow-up game and the credentials reissued for it per seat.
+    rematch: Option<(u64, [Option<String>; 4])>,
+    /// Keys for the god view, handed out to observers by the server operator.
+    observer_keys: HashSet<String>,
+    /// Open spectator event streams.
+    spectators: usize,
+    spectating_allowed: bool,
+    /// Kept secret until the game is over, only its commitment is public.
+    server_seed: [u8; 32],
+    commitment: String,
+    /// Seeds the players mixed into the server seed when they joined.
+    seed_contributions: [Option<String>; 4],
+    /// Seats the host took the chat away from.
+    muted: [bool; 4],
+    /// Whether players may ask the AI for a move.
+    hints_allowed: bool,
+    /// When spectators last chatted, per address.
+    spectator_chat_times: HashMap<Option<IpAddr>, Instant>,
 }
 
 impl GameInner {
     pub fn get_player(&self, auth: &str) -> Option<usize> {
         let (player, _) = self
-            .players
+            .seats
             .iter()
             .enumerate()
-            .filter_map(|(i, p)| {
-                if let Player::RealPlayer(p) = p {
+            .filter_map(|(i, s)| {
+                if let Some(Seat {
+                    player: Player::RealPlayer(p),
+                    ..
+                }) = s
+                {
                     Some((i, p))
                 } else {
                     None
@@ -47,295 +318,2823 @@ impl GameInner {
             .find(|(_, p)| p == &auth)?;
         Some(player)
     }
+
+    pub fn seats_taken(&self) -> usize {
+        self.seats.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Returns `preferred` if that seat is free, any free seat otherwise.
+    pub fn free_seat(&self, preferred: Option<usize>) -> Option<usize> {
+        preferred
+            .filter(|p| self.seats.get(*p).is_some_and(|s| s.is_none()))
+            .or_else(|| self.seats.iter().position(|s| s.is_none()))
+    }
+
+    /// Like `free_seat`, but for seats left in a running game.
+    pub fn open_seat(&self, preferred: Option<usize>) -> Option<usize> {
+        let is_open = |s: &Option<Seat>| s.as_ref().is_some_and(|s| s.open);
+        preferred
+            .filter(|p| self.seats.get(*p).is_some_and(is_open))
+            .or_else(|| self.seats.iter().position(is_open))
+    }
+
+    pub fn is_human(&self, player: usize) -> bool {
+        matches!(
+            self.seats.get(player),
+            Some(Some(Seat {
+                player: Player::RealPlayer(_),
+                ..
+            }))
+        )
+    }
+
+    pub fn is_host(&self, player: usize) -> bool {
+        self.host == Some(player)
+    }
+
+    /// Like `get_player`, but only accepts the host.
+    pub fn get_host(&self, auth: &str) -> Result<usize, ServerError> {
+        let player = self.get_player(auth).ok_or(ServerError::InvalidAuth)?;
+        if !self.is_host(player) {
+            Err(ServerError::Forbidden)?
+        }
+        Ok(player)
+    }
+
+    /// Checks whether someone presenting `invite` or `password` may join.
+    pub fn may_join(&self, invite: Option<&str>, password: Option<&str>) -> bool {
+        !self.settings.private
+            || invite.is_some_and(|i| self.invites.contains(i))
+            || password.is_some_and(|p| self.settings.password.as_deref() == Some(p))
+    }
+
+    /// AI players are always connected.
+    pub fn is_connected(&self, player: usize) -> bool {
+        match &self.seats[player] {
+            Some(Seat {
+                player: Player::AI(_),
+                ..
+            }) => true,
+            _ => self.connections[player] > 0,
+        }
+    }
+
+    /// Returns the AI currently in control of `player`, if any.
+    pub fn get_ai(&mut self, player: usize) -> Option<&mut AIPlayer> {
+        match &mut self.seats[player] {
+            Some(Seat {
+                player: Player::AI(ai),
+                ..
+            }) => Some(ai),
+            _ => self.stand_ins[player].as_mut(),
+        }
+    }
+
+    pub fn is_ai_controlled(&self, player: usize) -> bool {
+        match &self.seats[player] {
+            Some(Seat {
+                player: Player::AI(_),
+                ..
+            }) => true,
+            _ => self.stand_ins[player].is_some(),
+        }
+    }
+
+    /// Whether `player` still takes part in the game and may vote.
+    pub fn is_active(&self, player: usize) -> bool {
+        self.is_human(player) && !self.state.players[player].forfeited
+    }
+
+    /// Returns what a majority of the active players voted for, if anything.
+    pub fn vote_result(&self) -> Option<Vote> {
+        let voters = (0..4).filter(|p| self.is_active(*p)).count();
+        [Vote::Abort, Vote::End].iter().copied().find(|vote| {
+            let votes = (0..4)
+                .filter(|p| self.is_active(*p) && self.votes[*p] == Some(*vote))
+                .count();
+            votes * 2 > voters
+        })
+    }
+
+    /// Amount of spectators, `None` if spectating is disabled.
+    pub fn spectator_count(&self) -> Option<usize> {
+        Some(self.spectators).filter(|_| self.spectating_allowed)
+    }
+
+    /// Charges the time since the clock was last started to the current
+    /// player.
+    pub fn run_clock(&mut self) {
+        if let Some(started) = self.clock_started {
+            let now = Instant::now();
+            let player = self.state.round_state.player;
+            self.clocks[player] = self.clocks[player].saturating_sub(now - started);
+            self.clock_started = Some(now);
+        }
+    }
+
+    /// Charges the clock a last time and stops it, once an AI plays the
+    /// current turn.
+    pub fn stop_clock(&mut self) {
+        self.run_clock();
+        self.clock_started = None;
+    }
+
+    /// Checks what the game state can't tell, whether `player` controls
+    /// their seat and has time left. Charges the clock first, like
+    /// performing the action would.
+    pub fn check_may_act(&mut self, player: usize) -> Result<(), &'static str> {
+        if !self.is_started {
+            Err("the game has not started yet")?
+        }
+        if self.outcome.is_some() {
+            Err("the game is over")?
+        }
+        self.run_clock();
+        if self.is_ai_controlled(player) {
+            Err("an AI is playing for you")?
+        }
+        let on_turn = player == self.state.round_state.player;
+        if self.settings.time_bank.is_some() && on_turn && self.clocks[player] == Duration::ZERO {
+            Err("your clock ran out")?
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            // The actions are revealed with the outcome.
+            state: self.state.clone_without_log(),
+            names: self.seats.clone().map(|s| s.map(|s| s.name)),
+            ready: if self.is_started {
+                None
+            } else {
+                Some(self.seats.clone().map(|s| s.is_some_and(|s| s.ready)))
+            },
+            connected: [0, 1, 2, 3].map(|p| self.is_connected(p)),
+            ai_controlled: [0, 1, 2, 3].map(|p| self.is_ai_controlled(p)),
+            turn_deadline: self.turn_deadline,
+            clocks: self.settings.time_bank.map(|_| Clocks {
+                remaining: self.clocks,
+                running: self
+                    .clock_started
+                    .map(|started| (self.state.round_state.player, started)),
+            }),
+            votes: self.votes,
+            muted: self.muted,
+            hints_allowed: self.hints_allowed,
+            outcome: self.outcome.clone(),
+            spectators: self.spectator_count(),
+            commitment: self.commitment.clone(),
+            seed_contributions: self.seed_contributions.clone(),
+        }
+    }
+
+    /// Returns all logged events after `last_event_id`, or `None` if some of
+    /// them are no longer in the log.
+    pub fn events_since(&self, last_event_id: u64) -> Option<Vec<(u64, GameEvent)>> {
+        let first_logged = self
+            .event_log
+            .front()
+            .map_or(self.last_event_id + 1, |(id, _)| *id);
+        if last_event_id > self.last_event_id || last_event_id + 1 < first_logged {
+            None?
+        }
+        Some(
+            self.event_log
+                .iter()
+                .filter(|(id, _)| *id > last_event_id)
+                .cloned()
+                .collect(),
+        )
+    }
 }
 
 impl Game {
-    pub fn new(ai_player_count: usize) -> Arc<Game> {
-        let (sender, _) = broadcast::channel(16);
+    pub fn new(
+        ai_player_count: usize,
+        settings: GameSettings,
+        config: &Arc<ServerConfig>,
+        ratings: &Arc<Ratings>,
+    ) -> Arc<Game> {
+        let (sender, _) = broadcast::channel(config.channel_capacity);
         assert!(ai_player_count <= 4);
+        let server_seed: [u8; 32] = rand::random();
+        let clocks = [settings.time_bank.unwrap_or_default(); 4];
+        let seats = [0, 1, 2, 3].map(|x| {
+            if x < ai_player_count {
+                Some(Seat::ai(x, settings.ai_difficulty))
+            } else {
+                None
+            }
+        });
         Arc::new(Game {
             creation_time: Instant::now(),
+            config: config.clone(),
+            ratings: ratings.clone(),
             notify_change: sender,
             inner: Mutex::new(GameInner {
                 state: GameState::initial(),
-                players: (0..ai_player_count)
-                    .map(|x| Player::AI(AIPlayer::new(x)))
-                    .collect(),
+                settings,
+                seats,
+                host: None,
+                creator: None,
+                invites: HashSet::new(),
                 is_started: false,
+                turn: 0,
+                turn_deadline: None,
+                clocks,
+                clock_started: None,
+                last_event_id: 0,
+                event_log: VecDeque::with_capacity(EVENT_LOG_SIZE),
+                connections: [0; 4],
+                disconnected_since: [None; 4],
+                stand_ins: Default::default(),
+                votes: [None; 4],
+                left_accounts: Vec::new(),
+                outcome: None,
+                first_player: 0,
+                rematch: None,
+                observer_keys: HashSet::new(),
+                spectators: 0,
+                spectating_allowed: true,
+                server_seed,
+                commitment: fairness::commit(&server_seed),
+                seed_contributions: Default::default(),
+                muted: [false; 4],
+                hints_allowed: true,
+                spectator_chat_times: HashMap::new(),
             }),
         })
     }
 
-    pub fn join_player(self: &Arc<Self>) -> Option<String> {
-        let mut inner = self.inner.lock().unwrap();
-        if inner.players.len() >= 4 {
+    /// Describes the game for the lobby, `None` if it can't be joined.
+    pub fn lobby_entry(&self, id: u64) -> Option<LobbyEntry> {
+        let inner = self.inner.lock().unwrap();
+        if inner.is_started || inner.seats_taken() >= 4 || inner.settings.private {
             None?
         }
-        let id = inner.players.len();
-        let credentials = format!("{}:{:016x}", id, rand::random::<u64>());
-        inner
-            .players
-            .push(Player::RealPlayer(base64::encode(&credentials)));
-        drop(inner);
-        self.check_start_game();
-        Some(credentials)
+        let created = SystemTime::now() - self.creation_time.elapsed();
+        Some(LobbyEntry {
+            id: format!("{:016x}", id),
+            seats_taken: inner.seats_taken(),
+            seats_free: 4 - inner.seats_taken(),
+            ai_players: inner
+                .seats
+                .iter()
+                .flatten()
+                .filter(|s| matches!(s.player, Player::AI(_)))
+                .count(),
+            created: created
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            variant: inner.settings.variant(),
+        })
     }
 
-    fn broadcast(self: &Arc<Self>, event: GameEvent) {
-        let _ = self.notify_change.send(event);
+    /// Describes how the game ended, `None` if it is still going.
+    pub fn history_entry(&self, id: u64) -> Option<HistoryEntry> {
+        let inner = self.inner.lock().unwrap();
+        let outcome = inner.outcome.as_ref()?;
+        if inner.settings.private {
+            None?
+        }
+        Some(HistoryEntry {
+            id: format!("{:016x}", id),
+            variant: inner.settings.variant(),
+            result: outcome_to_string(outcome),
+            names: inner
+                .seats
+                .iter()
+                .map(|s| s.as_ref().map_or(String::new(), |s| s.name.clone()))
+                .collect(),
+            accounts: inner
+                .seats
+                .iter()
+                .map(|s| s.as_ref().and_then(|s| s.account))
+                .collect(),
+            finished: outcome
+                .finished
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
     }
 
-    // returns true of the game is won.
-    pub fn perform_player_action(self: &Arc<Self>, player: usize, action: PlayerAction) -> bool {
+    pub fn join_player(
+        self: &Arc<Self>,
+        query: &JoinQuery,
+        identity: Option<Identity>,
+    ) -> Result<String, ServerError> {
         let mut inner = self.inner.lock().unwrap();
-        let state_bkup = inner.state.clone();
-        let result = match inner.state.perform_player_action(player, action.clone()) {
-            Ok(result) => result,
-            Err(err) => {
-                warn!("Error occurred while performing player action: {}", err);
-                inner.state = state_bkup;
-                PlayerActionResult::Nominal
-            },
+        if !inner.may_join(query.invite.as_deref(), query.password.as_deref()) {
+            Err(ServerError::InvalidAuth)?
+        }
+        let account = identity.as_ref().map(|i| i.id);
+        if account.is_some() && inner.seats.iter().flatten().any(|s| s.account == account) {
+            Err(ServerError::InvalidState)?
+        }
+        let preferred = query.seat.or_else(|| {
+            let suit = query.suit?;
+            inner.state.players.iter().position(|p| p.suit == suit)
+        });
+        let id = if inner.is_started {
+            inner.open_seat(preferred)
+        } else {
+            inner.free_seat(preferred)
         };
-
-        let state = inner.state.clone();
-        drop(inner);
-
-        self.broadcast(GameEvent::GameStateChanged(state));
-
-        match result {
-            PlayerActionResult::Nominal => false,
-            PlayerActionResult::NextPlayer(_) => {
-                self.check_play_ai();
-                false
+        let id = id.ok_or(ServerError::GameFull)?;
+        if let Some(seed) = &query.seed {
+            // The deal is fixed once the game started.
+            if inner.is_started || seed.len() > fairness::MAX_CONTRIBUTION_LEN {
+                Err(ServerError::InvalidState)?
             }
-            PlayerActionResult::GameWon(winner) => {
-                self.broadcast(GameEvent::GameWon(winner));
-                true
+        }
+        let name = match (&query.name, identity) {
+            (Some(name), _) => parse_name(name)?,
+            (None, Some(identity)) => identity.username,
+            (None, None) => format!("Player {}", id),
+        };
+        let credentials = format!("{}:{:016x}", id, rand::random::<u64>());
+        // Until they open a stream, the seat counts as disconnected.
+        inner.disconnected_since[id] = Some(Instant::now());
+        inner.seats[id] = Some(Seat {
+            player: Player::RealPlayer(base64::encode(&credentials)),
+            name,
+            // Joining a running game takes over from the AI right away.
+            ready: inner.is_started,
+            open: false,
+            account,
+        });
+        // Whoever takes over an open seat keeps the contribution the deal
+        // came from.
+        if !inner.is_started {
+            inner.seed_contributions[id] = query.seed.clone();
+        }
+        match inner.creator {
+            Some(creator) if account == Some(creator) => inner.host = Some(id),
+            Some(_) => {}
+            None => {
+                inner.host.get_or_insert(id);
             }
         }
+        if inner.is_started && id == inner.state.round_state.player {
+            self.schedule_takeover(&mut inner, id);
+        }
+        let snapshot = inner.snapshot();
+        drop(inner);
+        self.broadcast(GameEvent::GameStateChanged(snapshot));
+        self.check_start_game();
+        Ok(credentials)
     }
 
-    pub fn check_start_game(self: &Arc<Self>) {
+    /// Changes the lobby before the game started and lets everyone know.
+    fn update_lobby(
+        self: &Arc<Self>,
+        update: impl FnOnce(&mut GameInner) -> Result<(), ServerError>,
+    ) -> Result<(), ServerError> {
         let mut inner = self.inner.lock().unwrap();
-        if !inner.is_started && inner.players.len() == 4 {
-            let state = inner.state.clone();
-            inner.is_started = true;
-            drop(inner);
-
-            self.broadcast(GameEvent::GameStateChanged(state));
-            self.check_play_ai();
+        if inner.is_started {
+            Err(ServerError::InvalidState)?
         }
+        update(&mut inner)?;
+        let snapshot = inner.snapshot();
+        drop(inner);
+        self.broadcast(GameEvent::GameStateChanged(snapshot));
+        self.check_start_game();
+        Ok(())
     }
 
-    pub fn check_play_ai(self: &Arc<Self>) {
+    pub fn set_ready(self: &Arc<Self>, player: usize, ready: bool) -> Result<(), ServerError> {
+        self.update_lobby(|inner| {
+            inner.seats[player].as_mut().unwrap().ready = ready;
+            Ok(())
+        })
+    }
+
+    pub fn add_ai(self: &Arc<Self>, seat: Option<usize>) -> Result<(), ServerError> {
+        self.update_lobby(|inner| {
+            let seat = match seat {
+                Some(seat) if inner.seats.get(seat).is_some_and(|s| s.is_some()) => {
+                    Err(ServerError::InvalidState)?
+                }
+                seat => inner.free_seat(seat).ok_or(ServerError::GameFull)?,
+            };
+            inner.seats[seat] = Some(Seat::ai(seat, inner.settings.ai_difficulty));
+            Ok(())
+        })
+    }
+
+    pub fn remove_ai(self: &Arc<Self>, seat: usize) -> Result<(), ServerError> {
+        self.update_lobby(|inner| match inner.seats.get(seat) {
+            Some(Some(Seat {
+                player: Player::AI(_),
+                ..
+            })) => {
+                inner.seats[seat] = None;
+                Ok(())
+            }
+            _ => Err(ServerError::InvalidState),
+        })
+    }
+
+    /// Removes `player` from the game. Before the game started the seat is
+    /// freed, afterwards an AI keeps it until someone else joins.
+    pub fn leave(self: &Arc<Self>, player: usize) {
         let mut inner = self.inner.lock().unwrap();
+        let was_ai_controlled = inner.is_ai_controlled(player);
         if inner.is_started {
-            let current = inner.state.round_state.player as usize;
-            let state = inner.state.clone();
-            let players = &mut inner.players;
-            if let Player::AI(ai) = &mut players[current] {
-                let moves = ai.play_turn(state);
-                let self2 = self.clone();
-                drop(inner);
-                tokio::spawn(async move {
-                    let mut interval = interval(Duration::from_secs(1));
-                    interval.tick().await;
-                    for m in moves {
-                        interval.tick().await;
-                        if self2.perform_player_action(current, m) {
-                            return;
-                        }
-                    }
-                });
+            if let Some(account) = inner.seats[player].as_ref().and_then(|s| s.account) {
+                inner.left_accounts.push(account);
+            }
+            inner.seats[player] = Some(Seat {
+                open: true,
+                ..Seat::ai(player, inner.settings.ai_difficulty)
+            });
+            inner.stand_ins[player] = None;
+            inner.votes[player] = None;
+            if player == inner.state.round_state.player {
+                inner.stop_clock();
             }
+        } else {
+            inner.seats[player] = None;
+            inner.seed_contributions[player] = None;
+        }
+        inner.muted[player] = false;
+        if inner.is_host(player) {
+            inner.host = (0..4).find(|p| inner.is_human(*p));
+        }
+        self.broadcast_locked(&mut inner, GameEvent::SeatLeft(player));
+        let snapshot = inner.snapshot();
+        self.broadcast_locked(&mut inner, GameEvent::GameStateChanged(snapshot));
+        drop(inner);
+
+        if !was_ai_controlled {
+            self.check_play_ai();
         }
     }
-}
 
-#[derive(Clone, Debug)]
-enum Player {
-    AI(AIPlayer),
-    RealPlayer(String),
-}
+    /// Lets the host remove a human player from the lobby.
+    pub fn kick(self: &Arc<Self>, host: usize, player: usize) -> Result<(), ServerError> {
+        let inner = self.inner.lock().unwrap();
+        if inner.is_started || player == host || !inner.is_human(player) {
+            Err(ServerError::InvalidState)?
+        }
+        drop(inner);
+        self.leave(player);
+        Ok(())
+    }
 
-#[derive(Clone, Debug)]
-enum GameEvent {
-    GameStateChanged(GameState),
-    GameWon(usize),
-}
+    /// Fills the empty seats with AI players and starts the game, once
+    /// everyone at the table is ready.
+    pub fn start_game(self: &Arc<Self>) -> Result<(), ServerError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_started || !inner.seats.iter().flatten().all(|s| s.ready) {
+            Err(ServerError::InvalidState)?
+        }
+        let difficulty = inner.settings.ai_difficulty;
+        for (i, seat) in inner.seats.iter_mut().enumerate() {
+            seat.get_or_insert_with(|| Seat::ai(i, difficulty));
+        }
+        self.begin_game(inner);
+        Ok(())
+    }
 
-impl GameEvent {
-    fn to_string(&self, player: usize) -> String {
-        match self {
-            GameEvent::GameStateChanged(state) => {
-                if player == state.round_state.player {
-                    format!(
-                        "state:{}\nhand:{}",
-                        state.to_string(),
-                        state.hand_to_string()
-                    )
-                } else {
-                    format!("state:{}", state.to_string())
-                }
+    /// Records the vote of `player`, `None` withdraws it. Ends the game once
+    /// a majority of the active players agrees.
+    pub fn vote(self: &Arc<Self>, player: usize, vote: Option<Vote>) -> Result<(), ServerError> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.is_started || inner.outcome.is_some() || !inner.is_active(player) {
+            Err(ServerError::InvalidState)?
+        }
+        // The players of a rated game could agree on its result.
+        if inner.settings.rated && vote == Some(Vote::End) {
+            Err(ServerError::Forbidden)?
+        }
+        inner.votes[player] = vote;
+        match inner.vote_result() {
+            Some(Vote::Abort) => self.end_game(&mut inner, GameResult::Aborted),
+            Some(Vote::End) => self.end_game(&mut inner, GameResult::Ended),
+            None => {
+                let snapshot = inner.snapshot();
+                self.broadcast_locked(&mut inner, GameEvent::GameStateChanged(snapshot));
             }
-            GameEvent::GameWon(winner) => format!("gmwon:{}", winner),
         }
+        Ok(())
     }
-}
 
-#[derive(Clone, Default)]
-pub struct Server {
-    games: Arc<RwLock<HashMap<u64, Arc<Game>>>>,
-}
+    /// Stops the game and its timers and records how it ended.
+    fn end_game(&self, inner: &mut GameInner, result: GameResult) {
+        inner.run_clock();
+        inner.turn += 1;
+        inner.turn_deadline = None;
+        inner.clock_started = None;
+        inner.votes = [None; 4];
+        let outcome = GameOutcome::new(inner, result);
+        inner.outcome = Some(outcome.clone());
+        if inner.settings.rated && result != GameResult::Aborted {
+            let state = &inner.state;
+            let mut place = 0;
+            let mut rated = Vec::new();
+            let mut ranking: Vec<(Entity, usize)> = (0..outcome.ranking.len())
+                .filter_map(|i| {
+                    let player = outcome.ranking[i];
+                    // Only the winner of a won game beats those tied with them.
+                    let tied = i > 0
+                        && !matches!(result, GameResult::Won(_) if i == 1)
+                        && state.standing(player) == state.standing(outcome.ranking[i - 1]);
+                    if !tied {
+                        place = i;
+                    }
+                    let entity = match inner.seats[player].as_ref()? {
+                        Seat {
+                            account: Some(account),
+                            ..
+                        } => Entity::Player(*account),
+                        Seat {
+                            player: Player::AI(_),
+                            open: false,
+                            ..
+                        } => Entity::AI(inner.settings.ai_difficulty),
+                        _ => None?,
+                    };
+                    // AI players of one difficulty are a single entity, rated
+                    // on the best place one of them finished on.
+                    if rated.contains(&entity) {
+                        None?
+                    }
+                    rated.push(entity);
+                    Some((entity, place))
+                })
+                .collect();
+            // Leaving doesn't save anyone from losing.
+            for account in &inner.left_accounts {
+                let entity = Entity::Player(*account);
+                if !rated.contains(&entity) {
+                    rated.push(entity);
+                    ranking.push((entity, outcome.ranking.len()));
+                }
+            }
+            if let Err(err) = self.ratings.update(&ranking) {
+                warn!("Could not save the ratings: {}", err);
+            }
+        }
 
-#[derive(Debug)]
-enum ServerError {
-    PathError,
-    InternalError,
-    GameNotFound,
-    InvalidAuth,
-}
-impl Display for ServerError {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        <Self as std::fmt::Debug>::fmt(self, fmt)
+        let snapshot = inner.snapshot();
+        self.broadcast_locked(inner, GameEvent::GameStateChanged(snapshot));
+        if let GameResult::Won(winner) = result {
+            self.broadcast_locked(inner, GameEvent::GameWon(winner));
+        }
+        self.broadcast_locked(inner, GameEvent::GameEnded(outcome));
     }
-}
-impl reject::Reject for ServerError {}
-impl std::error::Error for ServerError {}
-impl From<ServerError> for Rejection {
-    fn from(err: ServerError) -> Rejection {
-        reject::custom(err)
+
+    /// Creates a new key for the god view of this game.
+    pub fn create_observer_key(&self) -> String {
+        let key = format!("{:016x}", rand::random::<u64>());
+        self.inner.lock().unwrap().observer_keys.insert(key.clone());
+        key
     }
-}
 
-impl Server {
-    pub fn new() -> Server {
-        Default::default()
+    /// Sends a chat message of `player` to everyone at the table and the
+    /// spectators.
+    pub fn chat(self: &Arc<Self>, player: usize, text: &str) -> Result<(), ServerError> {
+        let text = parse_chat_message(text, MAX_CHAT_LEN)?;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.muted[player] {
+            Err(ServerError::Forbidden)?
+        }
+        self.broadcast_locked(&mut inner, GameEvent::Chat(player, text));
+        Ok(())
     }
 
-    pub fn add_test_game(&self, id: u64) {
-        let game = Game::new(4);
-        game.check_start_game();
-        self.games.write().unwrap().entry(id).or_insert(game);
+    pub fn emote(self: &Arc<Self>, player: usize, emote: Emote) -> Result<(), ServerError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.muted[player] {
+            Err(ServerError::Forbidden)?
+        }
+        self.broadcast_locked(&mut inner, GameEvent::Emote(player, emote));
+        Ok(())
     }
 
-    fn get_game_filter(&self) -> impl Filter<Extract = (Arc<Game>,), Error = Rejection> + Clone {
-        async fn get_game(this: Server, game_id: String) -> Result<Arc<Game>, Rejection> {
-            let id = u64::from_str_radix(&game_id, 16).map_err(|_| ServerError::PathError)?;
-            let games = this.games.read().unwrap();
-            let game = games.get(&id).ok_or(ServerError::GameNotFound)?;
-            Ok(game.clone())
+    /// Sends a chat message to the other spectators, the players don't get
+    /// to see it.
+    pub fn spectator_chat(
+        self: &Arc<Self>,
+        query: &SpectatorChatQuery,
+        addr: Option<IpAddr>,
+    ) -> Result<(), ServerError> {
+        let name = parse_name(&query.name)?;
+        let text = parse_chat_message(&query.text, MAX_SPECTATOR_CHAT_LEN)?;
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.spectating_allowed {
+            Err(ServerError::Forbidden)?
         }
-        let this = self.clone();
-        param().and_then(move |game_id| get_game(this.clone(), game_id))
+        if !inner.may_join(query.invite.as_deref(), query.password.as_deref()) {
+            Err(ServerError::InvalidAuth)?
+        }
+        let now = Instant::now();
+        let interval = self.config.spectator_chat_interval;
+        inner
+            .spectator_chat_times
+            .retain(|_, last| now.duration_since(*last) < interval);
+        match inner.spectator_chat_times.entry(addr) {
+            Entry::Occupied(_) => Err(ServerError::RateLimited)?,
+            Entry::Vacant(entry) => entry.insert(now),
+        };
+        self.broadcast_locked(&mut inner, GameEvent::SpectatorChat(name, text));
+        Ok(())
     }
 
-    fn auth_filter(&self) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
-        async fn parse_auth(auth: String) -> Result<String, Rejection> {
-            let mut iter = auth.split_ascii_whitespace();
-            let auth_type = iter.next().ok_or(ServerError::InvalidAuth)?;
-            let auth_str = iter.next().ok_or(ServerError::InvalidAuth)?;
-            if auth_type != "Basic" || iter.next().is_some() {
-                Err(ServerError::InvalidAuth)?
+    /// Lets the host take the chat away from another human player, or give
+    /// it back.
+    pub fn mute(
+        self: &Arc<Self>,
+        host: usize,
+        player: usize,
+        muted: bool,
+    ) -> Result<(), ServerError> {
+        let mut inner = self.inner.lock().unwrap();
+        if player == host || !inner.is_human(player) {
+            Err(ServerError::InvalidState)?
+        }
+        if inner.muted[player] != muted {
+            inner.muted[player] = muted;
+            let snapshot = inner.snapshot();
+            self.broadcast_locked(&mut inner, GameEvent::GameStateChanged(snapshot));
+        }
+        Ok(())
+    }
+
+    /// The move the AI would make for `player` now, with the reason for it.
+    pub fn hint(&self, player: usize) -> Result<(PlayerAction, String), ServerError> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.hints_allowed {
+            Err(ServerError::Forbidden)?
+        }
+        if !inner.is_started || inner.outcome.is_some() || inner.state.round_state.player != player
+        {
+            Err(ServerError::InvalidState)?
+        }
+        let ai = AIPlayer::with_difficulty(player, Difficulty::Hard);
+        Ok(ai.hint(inner.state.clone()))
+    }
+
+    /// Tells `player` what attacking would do, without doing it.
+    pub fn preview_attack(
+        &self,
+        player: usize,
+        query: &AttackQuery,
+    ) -> Result<AttackPreviewEntry, &'static str> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.is_started {
+            Err("the game has not started yet")?
+        }
+        if inner.outcome.is_some() {
+            Err("the game is over")?
+        }
+        let preview = inner
+            .state
+            .preview_attack(player, query.house_pile, query.target)?;
+        let (outcome, transferred) = match &preview.outcome {
+            AttackOutcome::Won(cards) => ("won", cards.to_string()),
+            AttackOutcome::Lost(cards) => ("lost", cards.to_string()),
+            AttackOutcome::Unopposed => ("unopposed", "00".to_owned()),
+        };
+        Ok(AttackPreviewEntry {
+            defending_pile: preview.defending_pile.map(|p| p.to_string()),
+            attack_value: preview.attack_value,
+            defense_value: preview.defense_value,
+            outcome,
+            transferred,
+        })
+    }
+
+    pub fn set_hints(self: &Arc<Self>, allowed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.hints_allowed != allowed {
+            inner.hints_allowed = allowed;
+            let snapshot = inner.snapshot();
+            self.broadcast_locked(&mut inner, GameEvent::GameStateChanged(snapshot));
+        }
+    }
+
+    /// Creates a new invite code that can be used to join this game.
+    pub fn create_invite(&self) -> String {
+        let invite = format!("{:016x}", rand::random::<u64>());
+        self.inner.lock().unwrap().invites.insert(invite.clone());
+        invite
+    }
+
+    fn broadcast(self: &Arc<Self>, event: GameEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        self.broadcast_locked(&mut inner, event);
+    }
+
+    fn broadcast_locked(&self, inner: &mut GameInner, event: GameEvent) {
+        inner.last_event_id += 1;
+        let id = inner.last_event_id;
+        if inner.event_log.len() == EVENT_LOG_SIZE {
+            inner.event_log.pop_front();
+        }
+        inner.event_log.push_back((id, event.clone()));
+        let _ = self.notify_change.send((id, event));
+    }
+
+    // returns true of the game is won.
+    pub fn perform_player_action(self: &Arc<Self>, player: usize, action: PlayerAction) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.outcome.is_some() {
+            return true;
+        }
+        inner.run_clock();
+        let state_bkup = inner.state.clone();
+        let result = match inner.state.perform_player_action(player, action.clone()) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("Error occurred while performing player action: {}", err);
+                inner.state = state_bkup;
+                PlayerActionResult::Nominal
+            },
+        };
+        self.finish_update(inner, player, result)
+    }
+
+    /// Performs all of `actions` or, if one of them fails, none of them and
+    /// tells everyone about the result once. Returns the index of the failed
+    /// action and why it failed.
+    pub fn perform_player_actions(
+        self: &Arc<Self>,
+        player: usize,
+        actions: Vec<PlayerAction>,
+    ) -> Result<(), (usize, &'static str)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.check_may_act(player).map_err(|err| (0, err))?;
+        let mut state = inner.state.clone();
+        let mut result = PlayerActionResult::Nominal;
+        for (i, action) in actions.into_iter().enumerate() {
+            if let PlayerActionResult::GameWon(_) = result {
+                Err((i, "the game is already won"))?
             }
-            Ok(auth_str.to_owned())
+            result = state
+                .perform_player_action(player, action)
+                .map_err(|err| (i, err))?;
         }
-        warp::header("Authorization").and_then(|auth: String| parse_auth(auth))
+        inner.state = state;
+        self.finish_update(inner, player, result);
+        Ok(())
     }
 
-    fn map_game_event_stream(game: Arc<Game>, auth: &str) -> Result<impl Reply, Rejection> {
-        let inner = game.inner.lock().unwrap();
+    /// Checks whether `action` would succeed, on a copy of the state so
+    /// nothing changes and nobody is told.
+    pub fn validate_player_action(
+        &self,
+        player: usize,
+        action: PlayerAction,
+    ) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.check_may_act(player)?;
+        let mut state = inner.state.clone();
+        drop(inner);
+        state.perform_player_action(player, action)?;
+        Ok(())
+    }
+
+    /// Broadcasts the state after `player` changed it and moves the game on
+    /// accordingly. Returns true if the game is won.
+    fn finish_update(
+        self: &Arc<Self>,
+        mut inner: MutexGuard<GameInner>,
+        player: usize,
+        result: PlayerActionResult,
+    ) -> bool {
+        if let PlayerActionResult::GameWon(winner) = result {
+            self.end_game(&mut inner, GameResult::Won(winner));
+            return true;
+        }
+        if let PlayerActionResult::NextPlayer(_) = result {
+            if !inner.state.players[player].forfeited {
+                let increment = inner.settings.increment;
+                inner.clocks[player] += increment;
+            }
+            self.start_turn(&mut inner);
+        }
+
+        let snapshot = inner.snapshot();
+        drop(inner);
+
+        self.broadcast(GameEvent::GameStateChanged(snapshot));
+
+        if let PlayerActionResult::NextPlayer(_) = result {
+            self.check_play_ai();
+        }
+        false
+    }
+
+    /// Starts games set to start on their own once every seat is taken and
+    /// ready, the others are started by their host.
+    pub fn check_start_game(self: &Arc<Self>) {
+        let inner = self.inner.lock().unwrap();
+        let all_ready = inner
+            .seats
+            .iter()
+            .all(|s| s.as_ref().is_some_and(|s| s.ready));
+        if inner.settings.auto_start && !inner.is_started && all_ready {
+            self.begin_game(inner);
+        }
+    }
+
+    /// Deals the cards and hands the first turn out.
+    fn begin_game(self: &Arc<Self>, mut inner: MutexGuard<GameInner>) {
+        inner.is_started = true;
+        let seed = fairness::derive_seed(&inner.server_seed, &inner.seed_contributions);
+        inner.state = GameState::with_seed(inner.first_player, seed);
+        self.start_turn(&mut inner);
+        let snapshot = inner.snapshot();
+        drop(inner);
+
+        self.broadcast(GameEvent::GameStateChanged(snapshot));
+        self.check_play_ai();
+    }
+
+    /// Starts the turn timer and clock of the current player, if the game has
+    /// them.
+    fn start_turn(self: &Arc<Self>, inner: &mut GameInner) {
+        inner.turn += 1;
+        inner.turn_deadline = None;
+        inner.clock_started = None;
+
+        let player = inner.state.round_state.player;
+        if inner.is_ai_controlled(player) {
+            return;
+        }
+        if !inner.is_connected(player) {
+            self.schedule_takeover(inner, player);
+        }
+        if inner.settings.time_bank.is_some() {
+            inner.clock_started = Some(Instant::now());
+            let remaining = inner.clocks[player];
+            let turn = inner.turn;
+            let self2 = self.clone();
+            tokio::spawn(async move {
+                delay_for(remaining).await;
+                self2.check_clock(turn);
+            });
+        }
+        if let Some(turn_time) = inner.settings.turn_time {
+            inner.turn_deadline = Some(Instant::now() + turn_time);
+            let turn = inner.turn;
+            let self2 = self.clone();
+            tokio::spawn(async move {
+                delay_for(turn_time).await;
+                self2.check_turn_timeout(turn);
+            });
+        }
+    }
+
+    /// Ends `turn` for the player if they are still on it.
+    fn check_turn_timeout(self: &Arc<Self>, turn: u64) {
+        let inner = self.inner.lock().unwrap();
+        let player = inner.state.round_state.player;
+        // An AI that took over the seat during the turn finishes it.
+        if inner.turn != turn || inner.is_ai_controlled(player) {
+            return;
+        }
+        let actions = match inner.settings.on_timeout {
+            TimeoutPolicy::Discard => vec![PlayerAction::DiscardHand],
+            TimeoutPolicy::AIMove => AIPlayer::new(player).play_turn(inner.state.clone()),
+        };
+        drop(inner);
+
+        for action in actions {
+            if self.perform_player_action(player, action) {
+                return;
+            }
+        }
+    }
+
+    /// Penalizes the current player if their clock ran out during `turn`.
+    fn check_clock(self: &Arc<Self>, turn: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let player = inner.state.round_state.player;
+        // An AI that took over the seat during the turn finishes it.
+        if inner.turn != turn || inner.is_ai_controlled(player) {
+            return;
+        }
+        inner.run_clock();
+        match inner.settings.on_flag {
+            FlagPenalty::Discard => {
+                drop(inner);
+                self.perform_player_action(player, PlayerAction::DiscardHand);
+            }
+            FlagPenalty::Forfeit => {
+                let result = inner
+                    .state
+                    .perform_player_action(player, PlayerAction::Resign)
+                    .unwrap_or(PlayerActionResult::Nominal);
+                self.finish_update(inner, player, result);
+            }
+        }
+    }
+
+    /// Registers a new event stream of `player`, the returned guard
+    /// unregisters it again once dropped.
+    fn connect(self: &Arc<Self>, inner: &mut GameInner, player: usize) -> ConnectionGuard {
+        inner.connections[player] += 1;
+        if inner.connections[player] == 1 {
+            inner.disconnected_since[player] = None;
+            self.broadcast_locked(inner, GameEvent::PresenceChanged(player, true));
+        }
+        if inner.stand_ins[player].take().is_some() {
+            self.broadcast_locked(inner, GameEvent::TakeoverChanged(player, false));
+        }
+        ConnectionGuard {
+            game: self.clone(),
+            player: Some(player),
+        }
+    }
+
+    /// Registers a new spectator event stream.
+    fn connect_spectator(self: &Arc<Self>, inner: &mut GameInner) -> ConnectionGuard {
+        inner.spectators += 1;
+        let spectators = inner.spectator_count();
+        self.broadcast_locked(inner, GameEvent::SpectatorsChanged(spectators));
+        ConnectionGuard {
+            game: self.clone(),
+            player: None,
+        }
+    }
+
+    fn disconnect_spectator(self: &Arc<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.spectators -= 1;
+        if inner.spectating_allowed {
+            let spectators = inner.spectator_count();
+            self.broadcast_locked(&mut inner, GameEvent::SpectatorsChanged(spectators));
+        }
+    }
+
+    /// Lets the host allow or disallow spectating, disallowing it closes the
+    /// open spectator streams.
+    pub fn set_spectating(self: &Arc<Self>, allowed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.spectating_allowed != allowed {
+            inner.spectating_allowed = allowed;
+            let spectators = inner.spectator_count();
+            self.broadcast_locked(&mut inner, GameEvent::SpectatorsChanged(spectators));
+        }
+    }
+
+    fn disconnect(self: &Arc<Self>, player: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.connections[player] -= 1;
+        if inner.connections[player] == 0 {
+            inner.disconnected_since[player] = Some(Instant::now());
+            self.broadcast_locked(&mut inner, GameEvent::PresenceChanged(player, false));
+            self.schedule_takeover(&mut inner, player);
+        }
+    }
+
+    /// Checks for a takeover once `player` has been away for the grace
+    /// period.
+    fn schedule_takeover(self: &Arc<Self>, inner: &mut GameInner, player: usize) {
+        let since = *inner.disconnected_since[player].get_or_insert_with(Instant::now);
+        let remaining = self
+            .config
+            .takeover_grace_period
+            .saturating_sub(since.elapsed());
+        let self2 = self.clone();
+        tokio::spawn(async move {
+            delay_for(remaining).await;
+            self2.check_takeover(player);
+        });
+    }
+
+    /// Lets an AI stand in for `player` if they are still disconnected.
+    fn check_takeover(self: &Arc<Self>, player: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let grace_period = self.config.takeover_grace_period;
+        let grace_period_over = matches!(
+            inner.disconnected_since[player],
+            Some(since) if since.elapsed() >= grace_period
+        );
+        if inner.is_started && grace_period_over && !inner.is_ai_controlled(player) {
+            inner.stand_ins[player] = Some(AIPlayer::new(player));
+            self.broadcast_locked(&mut inner, GameEvent::TakeoverChanged(player, true));
+            // Otherwise the stand-in plays once it's their turn.
+            let on_turn = player == inner.state.round_state.player;
+            if on_turn {
+                inner.stop_clock();
+            }
+            drop(inner);
+            if on_turn {
+                self.check_play_ai();
+            }
+        }
+    }
+
+    pub fn check_play_ai(self: &Arc<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_started && inner.outcome.is_none() {
+            let current = inner.state.round_state.player as usize;
+            let state = inner.state.clone();
+            if let Some(ai) = inner.get_ai(current) {
+                // The moves are made one by one rather than as a batch, so
+                // the others can follow along.
+                let moves = ai.play_turn(state);
+                let self2 = self.clone();
+                drop(inner);
+                tokio::spawn(async move {
+                    let mut interval = interval(Duration::from_secs(1));
+                    interval.tick().await;
+                    for m in moves {
+                        interval.tick().await;
+                        // The human player might have taken back control.
+                        if !self2.inner.lock().unwrap().is_ai_controlled(current) {
+                            return;
+                        }
+                        if self2.perform_player_action(current, m) {
+                            return;
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Player {
+    AI(AIPlayer),
+    RealPlayer(String),
+}
+
+#[derive(Clone, Debug)]
+struct Seat {
+    player: Player,
+    name: String,
+    ready: bool,
+    /// Left by a human player mid-game, anyone joining takes over from the
+    /// AI.
+    open: bool,
+    /// Id of the account the player joined with.
+    account: Option<u64>,
+}
+
+impl Seat {
+    fn ai(player: usize, difficulty: Difficulty) -> Seat {
+        Seat {
+            player: Player::AI(AIPlayer::with_difficulty(player, difficulty)),
+            name: "AI".to_owned(),
+            ready: true,
+            open: false,
+            account: None,
+        }
+    }
+}
+
+/// Chat messages are single lines, so they can't break up the events.
+fn parse_chat_message(text: &str, max_len: usize) -> Result<String, ServerError> {
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > max_len || text.chars().any(char::is_control) {
+        Err(ServerError::InvalidMessage)?
+    }
+    Ok(text.to_owned())
+}
+
+/// Display names are shown to the whole table, so only a small set of
+/// characters is allowed.
+fn parse_name(name: &str) -> Result<String, ServerError> {
+    let name = name.trim();
+    let valid_char = |c: char| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_';
+    if name.is_empty() || name.chars().count() > 20 || !name.chars().all(valid_char) {
+        Err(ServerError::InvalidName)?
+    }
+    Ok(name.to_owned())
+}
+
+/// Keeps a seat marked as connected while an event stream of it is open.
+#[derive(Debug)]
+struct ConnectionGuard {
+    game: Arc<Game>,
+    /// `None` for spectators.
+    player: Option<usize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        match self.player {
+            Some(player) => self.game.disconnect(player),
+            None => self.game.disconnect_spectator(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Clocks {
+    remaining: [Duration; 4],
+    /// The player whose clock is running and since when.
+    running: Option<(usize, Instant)>,
+}
+
+fn clocks_to_string(clocks: &Clocks) -> String {
+    let mut remaining = clocks.remaining;
+    if let Some((player, started)) = clocks.running {
+        remaining[player] = remaining[player].saturating_sub(started.elapsed());
+    }
+    let remaining: Vec<_> = remaining
+        .iter()
+        .map(|r| r.as_millis().to_string())
+        .collect();
+    remaining.join(",")
+}
+
+/// Everything a client needs to rebuild its view of the table.
+#[derive(Clone, Debug)]
+struct Snapshot {
+    state: GameState,
+    names: [Option<String>; 4],
+    /// Which seats are ready, only while the game did not start yet.
+    ready: Option<[bool; 4]>,
+    connected: [bool; 4],
+    ai_controlled: [bool; 4],
+    turn_deadline: Option<Instant>,
+    clocks: Option<Clocks>,
+    votes: [Option<Vote>; 4],
+    muted: [bool; 4],
+    hints_allowed: bool,
+    outcome: Option<GameOutcome>,
+    spectators: Option<usize>,
+    /// Hash of the server seed.
+    commitment: String,
+    seed_contributions: [Option<String>; 4],
+}
+
+#[derive(Clone, Debug)]
+enum GameEvent {
+    GameStateChanged(Snapshot),
+    GameWon(usize),
+    PresenceChanged(usize, bool),
+    TakeoverChanged(usize, bool),
+    /// Ends the event streams of that seat.
+    SeatLeft(usize),
+    GameEnded(GameOutcome),
+    /// The id of the rematch and the credentials for it per seat.
+    Rematch(u64, [Option<String>; 4]),
+    /// `None` once spectating is disabled, which ends the spectators' event
+    /// streams.
+    SpectatorsChanged(Option<usize>),
+    Chat(usize, String),
+    Emote(usize, Emote),
+    /// A chat message of a spectator, by name, only sent to spectators.
+    SpectatorChat(String, String),
+}
+
+/// Renders the event with everything hidden from the players, for the god
+/// view.
+fn god_view_to_string(event: &GameEvent) -> String {
+    let mut s = event.to_string(None);
+    if let GameEvent::GameStateChanged(snapshot) = event {
+        s += "\n";
+        s += &snapshot.state.hidden_to_string();
+    }
+    s
+}
+
+fn spectators_to_string(spectators: Option<usize>) -> String {
+    spectators.map_or("off".to_owned(), |count| count.to_string())
+}
+
+fn seat_flag_to_string(player: usize, flag: bool) -> String {
+    format!("{}{}", player, if flag { '+' } else { '-' })
+}
+
+fn seat_flags_to_string(flags: &[bool]) -> String {
+    let mut s = String::new();
+    for (p, f) in flags.iter().enumerate() {
+        s += &seat_flag_to_string(p, *f);
+    }
+    s
+}
+
+impl GameEvent {
+    /// Renders the event for `player`, or for spectators if it is `None`.
+    fn to_string(&self, player: Option<usize>) -> String {
+        match self {
+            GameEvent::GameStateChanged(Snapshot {
+                state,
+                names,
+                ready,
+                connected,
+                ai_controlled,
+                turn_deadline,
+                clocks,
+                votes,
+                muted,
+                hints_allowed,
+                outcome,
+                spectators,
+                commitment,
+                seed_contributions,
+            }) => {
+                let mut s = format!("state:{}", state.to_string());
+                if player == Some(state.round_state.player) {
+                    s += &format!("\nhand:{}", state.hand_to_string());
+                }
+                let names: Vec<_> = names.iter().map(|n| n.as_deref().unwrap_or("")).collect();
+                s += &format!("\nnames:{}", names.join(","));
+                if let Some(ready) = ready {
+                    s += &format!("\nready:{}", seat_flags_to_string(ready));
+                }
+                s += &format!("\nprsnc:{}", seat_flags_to_string(connected));
+                s += &format!("\naictl:{}", seat_flags_to_string(ai_controlled));
+                if let Some(deadline) = turn_deadline {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    s += &format!("\ntimer:{}", remaining.as_millis());
+                }
+                if let Some(clocks) = clocks {
+                    s += &format!("\nclock:{}", clocks_to_string(clocks));
+                }
+                let forfeited: Vec<_> = state.players.iter().map(|p| p.forfeited).collect();
+                s += &format!("\nresgn:{}", seat_flags_to_string(&forfeited));
+                if votes.iter().any(Option::is_some) {
+                    s += "\nvotes:";
+                    for (p, vote) in votes.iter().enumerate() {
+                        let vote = match vote {
+                            Some(Vote::Abort) => 'a',
+                            Some(Vote::End) => 'e',
+                            None => '-',
+                        };
+                        s += &format!("{}{}", p, vote);
+                    }
+                }
+                if muted.iter().any(|m| *m) {
+                    s += &format!("\nmuted:{}", seat_flags_to_string(muted));
+                }
+                s += &format!("\nhints:{}", if *hints_allowed { "on" } else { "off" });
+                match outcome {
+                    Some(outcome) => {
+                        s += &format!("\ngmend:{}", outcome_to_string(outcome));
+                        s += &format!("\n{}", outcome.reveal);
+                    }
+                    None => s += &format!("\nseeds:{}", seeds_to_string(seed_contributions)),
+                }
+                s += &format!("\nspect:{}", spectators_to_string(*spectators));
+                s += &format!("\ncmmit:{}", commitment);
+                s
+            }
+            GameEvent::GameWon(winner) => format!("gmwon:{}", winner),
+            GameEvent::PresenceChanged(p, c) => format!("prsnc:{}", seat_flag_to_string(*p, *c)),
+            GameEvent::TakeoverChanged(p, a) => format!("aictl:{}", seat_flag_to_string(*p, *a)),
+            GameEvent::SeatLeft(p) => format!("leave:{}", p),
+            GameEvent::GameEnded(outcome) => {
+                format!("gmend:{}\n{}", outcome_to_string(outcome), outcome.reveal)
+            }
+            GameEvent::Rematch(id, credentials) => {
+                match player.and_then(|p| credentials[p].as_ref()) {
+                    Some(credentials) => format!("rmtch:{:016x}:{}", id, credentials),
+                    None => format!("rmtch:{:016x}", id),
+                }
+            }
+            GameEvent::SpectatorsChanged(spectators) => {
+                format!("spect:{}", spectators_to_string(*spectators))
+            }
+            GameEvent::Chat(p, text) => format!("chatm:{}:{}", p, text),
+            GameEvent::Emote(p, emote) => format!("emote:{}:{}", p, emote.as_str()),
+            GameEvent::SpectatorChat(name, text) => format!("schat:{}:{}", name, text),
+        }
+    }
+
+    /// Whether `player` gets to see the event, `None` for spectators.
+    fn is_visible_to(&self, player: Option<usize>) -> bool {
+        match self {
+            GameEvent::SpectatorChat(..) => player.is_none(),
+            _ => true,
+        }
+    }
+}
+
+/// Settings shared by all games of a `Server`.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Amount of events a game buffers for every subscriber. Subscribers
+    /// falling further behind are resynced with a fresh snapshot.
+    pub channel_capacity: usize,
+    /// Longest time an event stream stays silent before a keep-alive comment
+    /// is sent.
+    pub keep_alive_interval: Duration,
+    /// How long a human player may stay disconnected before an AI takes over
+    /// their seat. They get it back by reconnecting.
+    pub takeover_grace_period: Duration,
+    /// How long players wait in the matchmaking queue before the empty seats
+    /// of their table are filled with AI players.
+    pub queue_wait: Duration,
+    /// File the player accounts are kept in, without one they are lost when
+    /// the server stops.
+    pub accounts_path: Option<PathBuf>,
+    /// File the ratings are kept in.
+    pub ratings_path: Option<PathBuf>,
+    /// Shortest time between two chat messages of spectators from the same
+    /// address, so they can't flood the event log.
+    pub spectator_chat_interval: Duration,
+    /// Shortest delay a game may be created with for its god view.
+    pub min_god_view_delay: Duration,
+    /// Secret needed to hand out god view keys, without one nobody can.
+    pub observer_secret: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            channel_capacity: 16,
+            keep_alive_interval: Duration::from_secs(15),
+            takeover_grace_period: Duration::from_secs(60),
+            queue_wait: Duration::from_secs(30),
+            accounts_path: None,
+            ratings_path: None,
+            spectator_chat_interval: Duration::from_secs(5),
+            min_god_view_delay: Duration::from_secs(60),
+            observer_secret: None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Server {
+    games: Arc<RwLock<HashMap<u64, Arc<Game>>>>,
+    queue: Arc<Mutex<Vec<QueueTicket>>>,
+    accounts: Arc<Accounts>,
+    ratings: Arc<Ratings>,
+    config: Arc<ServerConfig>,
+}
+
+/// A player waiting in the matchmaking queue.
+#[derive(Debug)]
+struct QueueTicket {
+    id: u64,
+    name: Option<String>,
+    identity: Option<Identity>,
+    /// Amount of human players wanted at the table.
+    size: usize,
+    ai_difficulty: Difficulty,
+    since: Instant,
+    /// Receives the game id and the credentials once the table is formed.
+    sender: oneshot::Sender<(u64, String)>,
+}
+
+/// Takes a player out of the queue once their stream is dropped.
+#[derive(Debug)]
+struct QueueGuard {
+    queue: Arc<Mutex<Vec<QueueTicket>>>,
+    ticket: u64,
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        let ticket = self.ticket;
+        self.queue.lock().unwrap().retain(|t| t.id != ticket);
+    }
+}
+
+#[derive(Debug)]
+enum ServerError {
+    PathError,
+    InternalError,
+    GameNotFound,
+    GameFull,
+    InvalidAuth,
+    InvalidName,
+    InvalidMessage,
+    NameTaken,
+    InvalidState,
+    Forbidden,
+    RateLimited,
+}
+impl Display for ServerError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        <Self as std::fmt::Debug>::fmt(self, fmt)
+    }
+}
+impl reject::Reject for ServerError {}
+impl std::error::Error for ServerError {}
+impl From<AccountError> for ServerError {
+    fn from(err: AccountError) -> ServerError {
+        match err {
+            AccountError::NameTaken => ServerError::NameTaken,
+            AccountError::WrongPassword => ServerError::InvalidAuth,
+            AccountError::Io(err) => {
+                warn!("Could not save the accounts: {}", err);
+                ServerError::InternalError
+            }
+        }
+    }
+}
+impl From<ServerError> for Rejection {
+    fn from(err: ServerError) -> Rejection {
+        reject::custom(err)
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Default::default()
+    }
+
+    pub fn with_config(config: ServerConfig) -> Server {
+        let accounts = match &config.accounts_path {
+            Some(path) => Accounts::open(path.clone()).expect("Could not load the accounts"),
+            None => Accounts::default(),
+        };
+        let ratings = match &config.ratings_path {
+            Some(path) => Ratings::open(path.clone()).expect("Could not load the ratings"),
+            None => Ratings::default(),
+        };
+        Server {
+            games: Default::default(),
+            queue: Default::default(),
+            accounts: Arc::new(accounts),
+            ratings: Arc::new(ratings),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn add_test_game(&self, id: u64) {
+        let settings = GameSettings {
+            auto_start: true,
+            ..Default::default()
+        };
+        let game = Game::new(4, settings, &self.config, &self.ratings);
+        game.check_start_game();
+        self.games.write().unwrap().entry(id).or_insert(game);
+    }
+
+    fn get_game_filter(&self) -> impl Filter<Extract = (Arc<Game>,), Error = Rejection> + Clone {
+        async fn get_game(this: Server, game_id: String) -> Result<Arc<Game>, Rejection> {
+            let id = u64::from_str_radix(&game_id, 16).map_err(|_| ServerError::PathError)?;
+            let games = this.games.read().unwrap();
+            let game = games.get(&id).ok_or(ServerError::GameNotFound)?;
+            Ok(game.clone())
+        }
+        let this = self.clone();
+        param().and_then(move |game_id| get_game(this.clone(), game_id))
+    }
+
+    fn auth_filter(&self) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+        async fn parse_auth(auth: String) -> Result<String, Rejection> {
+            let mut iter = auth.split_ascii_whitespace();
+            let auth_type = iter.next().ok_or(ServerError::InvalidAuth)?;
+            let auth_str = iter.next().ok_or(ServerError::InvalidAuth)?;
+            if auth_type != "Basic" || iter.next().is_some() {
+                Err(ServerError::InvalidAuth)?
+            }
+            Ok(auth_str.to_owned())
+        }
+        warp::header("Authorization").and_then(|auth: String| parse_auth(auth))
+    }
+
+    /// Takes the username and the password of an account from basic Auth.
+    fn account_auth_filter(
+        &self,
+    ) -> impl Filter<Extract = (String, String), Error = Rejection> + Clone {
+        self.auth_filter()
+            .and_then(|auth: String| async move {
+                let decoded = base64::decode(&auth)
+                    .ok()
+                    .and_then(|d| String::from_utf8(d).ok());
+                let decoded = decoded.ok_or(ServerError::InvalidAuth)?;
+                let (username, password) =
+                    decoded.split_once(':').ok_or(ServerError::InvalidAuth)?;
+                let result: Result<_, Rejection> = Ok((username.to_owned(), password.to_owned()));
+                result
+            })
+            .untuple_one()
+    }
+
+    /// Looks up the account of a `Bearer` token, requests without one are
+    /// anonymous.
+    fn identity_filter(
+        &self,
+    ) -> impl Filter<Extract = (Option<Identity>,), Error = Rejection> + Clone {
+        let accounts = self.accounts.clone();
+        warp::header::optional("Authorization").and_then(move |auth: Option<String>| {
+            let token = auth
+                .as_deref()
+                .and_then(|auth| auth.strip_prefix("Bearer "));
+            let result = match token {
+                Some(token) => accounts
+                    .authenticate(token.trim())
+                    .map(Some)
+                    .ok_or(ServerError::InvalidAuth),
+                None => Ok(None),
+            };
+            future::ready(result.map_err(Rejection::from))
+        })
+    }
+
+    fn map_game_event_stream(
+        &self,
+        game: Arc<Game>,
+        auth: &str,
+        last_event_id: Option<u64>,
+    ) -> Result<impl Reply, Rejection> {
+        let mut inner = game.inner.lock().unwrap();
+
+        let player = inner.get_player(auth).ok_or(ServerError::InvalidAuth)?;
+
+        // Subscribe while holding the lock, so no event can slip in between
+        // the replayed events and the live ones.
+        let event_stream = game.notify_change.subscribe();
+        let guard = game.connect(&mut inner, player);
+        Ok(self.event_stream(inner, event_stream, guard, last_event_id))
+    }
+
+    /// Streams the public events of a game, without anyone's hand.
+    fn map_spectator_stream(
+        &self,
+        game: Arc<Game>,
+        query: &WatchQuery,
+        last_event_id: Option<u64>,
+    ) -> Result<impl Reply, Rejection> {
+        let mut inner = game.inner.lock().unwrap();
+        if !inner.spectating_allowed {
+            Err(ServerError::Forbidden)?
+        }
+        if !inner.may_join(query.invite.as_deref(), query.password.as_deref()) {
+            Err(ServerError::InvalidAuth)?
+        }
+
+        let event_stream = game.notify_change.subscribe();
+        let guard = game.connect_spectator(&mut inner);
+        Ok(self.event_stream(inner, event_stream, guard, last_event_id))
+    }
+
+    /// Streams all events of a game including every hand and the stock. It
+    /// lags behind by the god view delay, and without one it only opens once
+    /// the game is over.
+    fn map_god_view_stream(
+        &self,
+        game: Arc<Game>,
+        query: &ObserverQuery,
+        auth: Option<&str>,
+        identity: Option<Identity>,
+    ) -> Result<impl Reply, Rejection> {
+        let inner = game.inner.lock().unwrap();
+        if !inner.observer_keys.contains(&query.key) {
+            Err(ServerError::InvalidAuth)?
+        }
+        // Best effort only, a player can still leave out their credentials.
+        let account = identity.map(|i| i.id);
+        let is_seated = auth.and_then(|auth| inner.get_player(auth)).is_some()
+            || (account.is_some() && inner.seats.iter().flatten().any(|s| s.account == account));
+        if is_seated {
+            Err(ServerError::Forbidden)?
+        }
+        let delay = match (inner.outcome.as_ref(), inner.settings.god_view_delay) {
+            (Some(_), _) => Duration::from_secs(0),
+            (None, Some(delay)) => delay,
+            (None, None) => Err(ServerError::Forbidden)?,
+        };
+
+        // Events are timestamped as they come in, so the delay doesn't add
+        // up while earlier ones are held back.
+        let mut event_stream = game.notify_change.subscribe();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let snapshot = GameEvent::GameStateChanged(inner.snapshot());
+        let _ = sender.send((Instant::now(), god_view_to_string(&snapshot)));
+        drop(inner);
+        tokio::spawn(async move {
+            loop {
+                let event = match event_stream.recv().await {
+                    Ok((_, event)) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        GameEvent::GameStateChanged(game.inner.lock().unwrap().snapshot())
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if sender
+                    .send((Instant::now(), god_view_to_string(&event)))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let events = stream::unfold(receiver, move |mut receiver| async move {
+            let (received, data) = receiver.recv().await?;
+            delay_until(time::Instant::from_std(received + delay)).await;
+            Some((Ok::<_, ServerError>(sse::data(data)), receiver))
+        });
+        Ok(sse::reply(
+            sse::keep_alive()
+                .interval(self.config.keep_alive_interval)
+                .stream(events),
+        ))
+    }
+
+    /// Turns the events of a game into an event stream for the viewer of
+    /// `guard`, starting with the events after `last_event_id` or a snapshot.
+    fn event_stream(
+        &self,
+        inner: MutexGuard<GameInner>,
+        event_stream: broadcast::Receiver<(u64, GameEvent)>,
+        guard: ConnectionGuard,
+        last_event_id: Option<u64>,
+    ) -> impl Reply {
+        let replay = last_event_id
+            .and_then(|id| inner.events_since(id))
+            .unwrap_or_else(|| {
+                vec![(
+                    inner.last_event_id,
+                    GameEvent::GameStateChanged(inner.snapshot()),
+                )]
+            });
+        drop(inner);
+
+        let player = guard.player;
+        let mut last_sent_id = replay.first().map_or(0, |(id, _)| id.saturating_sub(1));
+        let replay_stream = stream::iter(replay.into_iter().map(Ok::<_, RecvError>));
+        // The seat could be taken by someone else after this, so stop
+        // streaming its private information. Spectators are sent away when
+        // spectating is disabled. Replayed events happened before the
+        // connection was accepted, so only live ones end the stream.
+        let live_stream = event_stream.take_while(move |event| {
+            let ended = match (event, player) {
+                (Ok((_, GameEvent::SeatLeft(p))), Some(player)) => *p == player,
+                (Ok((_, GameEvent::SpectatorsChanged(None))), None) => true,
+                _ => false,
+            };
+            future::ready(!ended)
+        });
+        let both = replay_stream.chain(live_stream);
+
+        let events = both.filter_map(move |event| {
+            let event = match event {
+                // Already covered by a resync snapshot.
+                Ok((id, _)) if id <= last_sent_id => None,
+                Ok((id, event)) => {
+                    last_sent_id = id;
+                    if event.is_visible_to(player) {
+                        Some(Ok((sse::id(id), sse::data(event.to_string(player)))))
+                    } else {
+                        None
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    let inner = guard.game.inner.lock().unwrap();
+                    last_sent_id = inner.last_event_id;
+                    let state = GameEvent::GameStateChanged(inner.snapshot());
+                    drop(inner);
+                    let data = format!("rsync:{}\n{}", missed, state.to_string(player));
+                    Some(Ok((sse::id(last_sent_id), sse::data(data))))
+                }
+                Err(RecvError::Closed) => Some(Err(ServerError::InternalError)),
+            };
+            future::ready(event)
+        });
+
+        sse::reply(
+            sse::keep_alive()
+                .interval(self.config.keep_alive_interval)
+                .stream(events),
+        )
+    }
+
+    /// Lists joinable games, newest first.
+    fn list_games(&self, query: &LobbyQuery) -> Vec<LobbyEntry> {
+        let games = self.games.read().unwrap();
+        let mut entries: Vec<_> = games
+            .iter()
+            .filter_map(|(id, game)| game.lobby_entry(*id))
+            .filter(|e| e.seats_free >= query.min_free_seats.unwrap_or(1))
+            .filter(|e| query.variant.as_ref().is_none_or(|v| v == &e.variant))
+            .collect();
+        drop(games);
+
+        entries.sort_by(|a, b| b.created.cmp(&a.created).then(a.id.cmp(&b.id)));
+        entries
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(20).min(100))
+            .collect()
+    }
+
+    fn list_history(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        let games = self.games.read().unwrap();
+        let mut entries: Vec<_> = games
+            .iter()
+            .filter_map(|(id, game)| game.history_entry(*id))
+            .filter(|e| query.variant.as_ref().is_none_or(|v| v == &e.variant))
+            .filter(|e| query.account.is_none_or(|a| e.accounts.contains(&Some(a))))
+            .collect();
+        drop(games);
+
+        entries.sort_by(|a, b| b.finished.cmp(&a.finished).then(a.id.cmp(&b.id)));
+        entries
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(20).min(100))
+            .collect()
+    }
+
+    /// Lists the ratings, best first.
+    fn leaderboard(&self, query: &LeaderboardQuery) -> Vec<LeaderboardEntry> {
+        self.ratings
+            .leaderboard()
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(20).min(100))
+            .map(|rating| {
+                let (name, account) = match rating.entity {
+                    Entity::Player(id) => {
+                        (self.accounts.username(id).unwrap_or_default(), Some(id))
+                    }
+                    Entity::AI(difficulty) => (format!("AI ({:?})", difficulty), None),
+                };
+                LeaderboardEntry {
+                    name,
+                    account,
+                    rating: rating.rating.round() as i64,
+                    games: rating.games,
+                }
+            })
+            .collect()
+    }
+
+    fn create_game(
+        &self,
+        ai_player_count: u8,
+        settings: GameSettings,
+        creator: Option<Identity>,
+    ) -> (u64, Arc<Game>) {
+        let game = Game::new(
+            ai_player_count as usize,
+            settings,
+            &self.config,
+            &self.ratings,
+        );
+        game.inner.lock().unwrap().creator = creator.map(|i| i.id);
+        game.check_start_game();
+        let id = Server::insert_game(&mut self.games.write().unwrap(), game.clone());
+        (id, game)
+    }
+
+    fn insert_game(games: &mut HashMap<u64, Arc<Game>>, game: Arc<Game>) -> u64 {
+        loop {
+            let id = rand::random();
+            match games.entry(id) {
+                Entry::Occupied(_) => continue,
+                Entry::Vacant(v) => v.insert(game.clone()),
+            };
+            break id;
+        }
+    }
+
+    /// Puts a player into the matchmaking queue. The returned receiver gets
+    /// the game id and their credentials once they have a table.
+    fn enqueue(
+        &self,
+        query: &QueueQuery,
+        identity: Option<Identity>,
+    ) -> Result<(QueueGuard, oneshot::Receiver<(u64, String)>), ServerError> {
+        let size = query.size.unwrap_or(4);
+        if size == 0 || size > 4 {
+            Err(ServerError::InvalidState)?
+        }
+        let name = query.name.as_deref().map(parse_name).transpose()?;
+        let mut queue = self.queue.lock().unwrap();
+        // An account can only take one seat at a table.
+        if let Some(identity) = &identity {
+            let account = Some(identity.id);
+            if queue
+                .iter()
+                .any(|t| t.identity.as_ref().map(|i| i.id) == account)
+            {
+                Err(ServerError::InvalidState)?
+            }
+        }
+        let (sender, receiver) = oneshot::channel();
+        let ticket = rand::random();
+        queue.push(QueueTicket {
+            id: ticket,
+            name,
+            identity,
+            size,
+            ai_difficulty: query.ai_difficulty.unwrap_or_default(),
+            since: Instant::now(),
+            sender,
+        });
+        drop(queue);
+        self.check_queue();
+
+        let self2 = self.clone();
+        tokio::spawn(async move {
+            delay_for(self2.config.queue_wait).await;
+            self2.check_queue();
+        });
+        let guard = QueueGuard {
+            queue: self.queue.clone(),
+            ticket,
+        };
+        Ok((guard, receiver))
+    }
+
+    /// Seats the queued players that have enough company, or waited long
+    /// enough to play with AI players instead.
+    fn check_queue(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        // Nobody is waiting for those tickets anymore.
+        queue.retain(|t| !t.sender.is_closed());
+        loop {
+            // The queue is in joining order, so the oldest tickets go first.
+            let table = queue.iter().find_map(|ticket| {
+                let table: Vec<u64> = queue
+                    .iter()
+                    .filter(|t| t.size == ticket.size && t.ai_difficulty == ticket.ai_difficulty)
+                    .map(|t| t.id)
+                    .take(ticket.size)
+                    .collect();
+                if table.len() == ticket.size || ticket.since.elapsed() >= self.config.queue_wait {
+                    Some(table)
+                } else {
+                    None
+                }
+            });
+            let table = match table {
+                Some(table) => table,
+                None => break,
+            };
+            let (tickets, rest) = queue.drain(..).partition(|t| table.contains(&t.id));
+            *queue = rest;
+            self.form_table(tickets);
+        }
+    }
+
+    /// Starts a game for the players of `tickets`, with AI players on the
+    /// remaining seats and on the seats of players who couldn't be seated.
+    fn form_table(&self, tickets: Vec<QueueTicket>) {
+        let settings = GameSettings {
+            ai_difficulty: tickets[0].ai_difficulty,
+            rated: true,
+            // The first player to join hosts the table, but it starts on its
+            // own once every seat is taken.
+            auto_start: true,
+            ..Default::default()
+        };
+        let game = Game::new(4 - tickets.len(), settings, &self.config, &self.ratings);
+        let id = Server::insert_game(&mut self.games.write().unwrap(), game.clone());
+        let mut unseated = 0;
+        for ticket in tickets {
+            let query = JoinQuery {
+                name: ticket.name,
+                seat: None,
+                suit: None,
+                invite: None,
+                password: None,
+                seed: None,
+            };
+            let credentials = match game.join_player(&query, ticket.identity) {
+                Ok(credentials) => credentials,
+                Err(err) => {
+                    warn!("Could not seat a queued player: {}", err);
+                    unseated += 1;
+                    continue;
+                }
+            };
+            let player = game
+                .inner
+                .lock()
+                .unwrap()
+                .get_player(&base64::encode(&credentials));
+            let player = player.unwrap();
+            game.set_ready(player, true).unwrap();
+            // They left the queue just now, an AI takes the seat.
+            if ticket.sender.send((id, credentials)).is_err() {
+                game.leave(player);
+                unseated += 1;
+            }
+        }
+        // Taking the last seat starts the game.
+        for _ in 0..unseated {
+            game.add_ai(None).unwrap();
+        }
+    }
+
+    /// Sets up a new game with the same table and rules once `game` is over,
+    /// `rotate` hands the first turn to the next seat. Every human player
+    /// gets new credentials, which are also sent on their event streams.
+    /// Returns the id of the new game and the credentials of `player`.
+    fn rematch(
+        &self,
+        game: &Arc<Game>,
+        player: usize,
+        rotate: bool,
+    ) -> Result<(u64, String), ServerError> {
+        // Same lock order as `list_games`, the games before the game.
+        let mut games = self.games.write().unwrap();
+        let mut inner = game.inner.lock().unwrap();
+        if inner.outcome.is_none() || !inner.is_human(player) {
+            Err(ServerError::InvalidState)?
+        }
+        if let Some((id, credentials)) = &inner.rematch {
+            let credentials = credentials[player].clone();
+            return Ok((*id, credentials.ok_or(ServerError::InternalError)?));
+        }
+
+        let rematch = Game::new(0, inner.settings.clone(), &self.config, &self.ratings);
+        let mut credentials: [Option<String>; 4] = Default::default();
+        {
+            let mut new_inner = rematch.inner.lock().unwrap();
+            for (i, seat) in inner.seats.iter().enumerate() {
+                new_inner.seats[i] = match seat {
+                    Some(Seat { open: true, .. }) | None => None,
+                    Some(Seat {
+                        player: Player::AI(_),
+                        ..
+                    }) => Some(Seat::ai(i, inner.settings.ai_difficulty)),
+                    Some(Seat {
+                        player: Player::RealPlayer(_),
+                        name,
+                        account,
+                        ..
+                    }) => {
+                        let creds = format!("{}:{:016x}", i, rand::random::<u64>());
+                        let seat = Seat {
+                            player: Player::RealPlayer(base64::encode(&creds)),
+                            name: name.clone(),
+                            ready: false,
+                            open: false,
+                            account: *account,
+                        };
+                        credentials[i] = Some(creds);
+                        Some(seat)
+                    }
+                };
+            }
+            new_inner.host = inner.host;
+            new_inner.creator = inner.creator;
+            new_inner.invites = inner.invites.clone();
+            new_inner.first_player = if rotate {
+                (inner.first_player + 1) % 4
+            } else {
+                inner.first_player
+            };
+            new_inner.state = GameState::starting_with(new_inner.first_player);
+        }
+        let id = Server::insert_game(&mut games, rematch.clone());
+        drop(games);
+
+        inner.rematch = Some((id, credentials.clone()));
+        game.broadcast_locked(&mut inner, GameEvent::Rematch(id, credentials.clone()));
+        drop(inner);
+        rematch.check_start_game();
+
+        let credentials = credentials[player].clone();
+        Ok((id, credentials.ok_or(ServerError::InternalError)?))
+    }
+
+    pub async fn serve(&self, addr: impl Into<SocketAddr> + 'static) {
+        let log = warp::log("web_api");
+        warp::serve(self.routes().with(log)).run(addr).await;
+    }
+
+    fn routes(&self) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let self2 = self.clone();
+        // GET server.com/api/v0/game/stream/123abc/ (with basic Auth, optional Last-Event-ID)
+        let stream = path("stream")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::get())
+            .and(self.auth_filter())
+            .and(sse::last_event_id::<u64>())
+            .and_then(move |x, auth: String, last_event_id| {
+                future::ready(self2.map_game_event_stream(x, &auth, last_event_id))
+            });
+        let self2 = self.clone();
+        // GET server.com/api/v0/game/watch/123abc/[?invite=456def]
+        //     (optional Game-Password header, optional Last-Event-ID)
+        let watch = path("watch")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::get())
+            .and(query())
+            .and(warp::header::optional("Game-Password"))
+            .and(sse::last_event_id::<u64>())
+            .and_then(
+                move |game, mut query: WatchQuery, password, last_event_id| {
+                    query.password = password;
+                    future::ready(self2.map_spectator_stream(game, &query, last_event_id))
+                },
+            );
+
+        let self2 = self.clone();
+        // GET server.com/api/v0/game/godview/123abc/?key=456def
+        //     (not with the basic Auth or bearer token of a player at the table)
+        let god_view = path("godview")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::get())
+            .and(query())
+            .and(warp::header::optional("Authorization"))
+            .and(self.identity_filter())
+            .and_then(
+                move |game, query: ObserverQuery, auth: Option<String>, identity| {
+                    let auth = auth
+                        .as_deref()
+                        .and_then(|auth| auth.strip_prefix("Basic "))
+                        .map(str::trim);
+                    future::ready(self2.map_god_view_stream(game, &query, auth, identity))
+                },
+            );
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/game/observer/123abc/ (with basic Auth of the observer secret)
+        // Responds with a key for the god view.
+        let create_observer_key = path("observer")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and_then(move |game: Arc<Game>, auth: String| {
+                let result = match &self2.config.observer_secret {
+                    Some(secret) if base64::encode(secret) == auth => {
+                        Ok(game.create_observer_key())
+                    }
+                    _ => Err(Rejection::from(ServerError::InvalidAuth)),
+                };
+                future::ready(result)
+            });
+
+        // POST server.com/api/v0/game/spectators/123abc/?allowed=false (with basic Auth, host only)
+        let spectators = path("spectators")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: SpectatorsQuery| async move {
+                    game.inner.lock().unwrap().get_host(&auth)?;
+                    game.set_spectating(query.allowed);
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/chat/123abc/?text=hello (with basic Auth)
+        let chat = path("chat")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: ChatQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    let player = player.ok_or(ServerError::InvalidAuth)?;
+                    game.chat(player, &query.text)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/emote/123abc/?emote=gg (with basic Auth)
+        let emote = path("emote")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: EmoteQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    let player = player.ok_or(ServerError::InvalidAuth)?;
+                    game.emote(player, query.emote)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/spectatorchat/123abc/?name=Bob&text=hello[&invite=456def]
+        //     (optional Game-Password header)
+        let spectator_chat = path("spectatorchat")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(query())
+            .and(warp::header::optional("Game-Password"))
+            .and(warp::addr::remote())
+            .and_then(
+                |game: Arc<Game>,
+                 mut query: SpectatorChatQuery,
+                 password,
+                 addr: Option<SocketAddr>| async move {
+                    query.password = password;
+                    game.spectator_chat(&query, addr.map(|addr| addr.ip()))?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/mute/123abc/?seat=2[&muted=false] (with basic Auth, host only)
+        let mute = path("mute")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: MuteQuery| async move {
+                    let host = game.inner.lock().unwrap().get_host(&auth)?;
+                    game.mute(host, query.seat, query.muted.unwrap_or(true))?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // GET server.com/api/v0/game/hint/123abc/ (with basic Auth)
+        // Responds with the suggested action and the reason for it on the next line.
+        let hint = path("hint")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::get())
+            .and(self.auth_filter())
+            .and_then(|game: Arc<Game>, auth: String| async move {
+                let player = game.inner.lock().unwrap().get_player(&auth);
+                let player = player.ok_or(ServerError::InvalidAuth)?;
+                let (action, explanation) = game.hint(player)?;
+                let result: Result<String, Rejection> =
+                    Ok(format!("{}\n{}", action.to_string(), explanation));
+                result
+            });
+
+        // GET server.com/api/v0/game/attack/123abc/?house_pile=1&target=s (with basic Auth)
+        // Responds with `valid` false and the reason if the attack isn't
+        // possible.
+        let attack_preview = path("attack")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::get())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: AttackQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    let player = player.ok_or(ServerError::InvalidAuth)?;
+                    let reply = match game.preview_attack(player, &query) {
+                        Ok(preview) => warp::reply::json(&preview),
+                        Err(error) => warp::reply::json(&Validation {
+                            valid: false,
+                            error: Some(error),
+                        }),
+                    };
+                    let result: Result<_, Rejection> = Ok(reply);
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/hints/123abc/?allowed=false (with basic Auth, host only)
+        let hints = path("hints")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: HintsQuery| async move {
+                    game.inner.lock().unwrap().get_host(&auth)?;
+                    game.set_hints(query.allowed);
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/join/123abc/?name=Alice[&seat=2 or &suit=d]
+        //     [&invite=456def][&seed=xyz] (optional Game-Password header, optionally with bearer token)
+        let join = path("join")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(query())
+            .and(warp::header::optional("Game-Password"))
+            .and(self.identity_filter())
+            .and_then(
+                |game: Arc<Game>, mut query: JoinQuery, password, identity| async move {
+                    query.password = password;
+                    match game.join_player(&query, identity) {
+                        Ok(credentials) => Ok(credentials),
+                        Err(ServerError::GameFull) => Ok("Error".to_string()),
+                        Err(err) => Err(Rejection::from(err)),
+                    }
+                },
+            );
+
+        // POST server.com/api/v0/game/invite/123abc/ (with basic Auth, host only)
+        let create_invite = path("invite")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and_then(|game: Arc<Game>, auth: String| async move {
+                game.inner.lock().unwrap().get_host(&auth)?;
+                let result: Result<String, Rejection> = Ok(game.create_invite());
+                result
+            });
+
+        // DELETE server.com/api/v0/game/invite/123abc/?invite=456def (with basic Auth, host only)
+        let revoke_invite = path("invite")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::delete())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: InviteQuery| async move {
+                    let mut inner = game.inner.lock().unwrap();
+                    inner.get_host(&auth)?;
+                    inner.invites.remove(&query.invite);
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/ready/123abc/[?ready=false] (with basic Auth)
+        let ready = path("ready")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: ReadyQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    let player = player.ok_or(ServerError::InvalidAuth)?;
+                    game.set_ready(player, query.ready.unwrap_or(true))?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/ai/123abc/[?seat=2] (with basic Auth, host only)
+        let add_ai = path("ai")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: SeatQuery| async move {
+                    game.inner.lock().unwrap().get_host(&auth)?;
+                    game.add_ai(query.seat)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // DELETE server.com/api/v0/game/ai/123abc/?seat=2 (with basic Auth, host only)
+        let remove_ai = path("ai")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::delete())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: SeatQuery| async move {
+                    game.inner.lock().unwrap().get_host(&auth)?;
+                    game.remove_ai(query.seat.ok_or(ServerError::PathError)?)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/start/123abc/ (with basic Auth, host only)
+        let start = path("start")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and_then(|game: Arc<Game>, auth: String| async move {
+                game.inner.lock().unwrap().get_host(&auth)?;
+                game.start_game()?;
+                let result: Result<&'static str, Rejection> = Ok("success");
+                result
+            });
+
+        // POST server.com/api/v0/game/leave/123abc/ (with basic Auth)
+        let leave = path("leave")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and_then(|game: Arc<Game>, auth: String| async move {
+                let player = game.inner.lock().unwrap().get_player(&auth);
+                game.leave(player.ok_or(ServerError::InvalidAuth)?);
+                let result: Result<&'static str, Rejection> = Ok("success");
+                result
+            });
+
+        // POST server.com/api/v0/game/kick/123abc/?seat=2 (with basic Auth, host only)
+        let kick = path("kick")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: SeatQuery| async move {
+                    let host = game.inner.lock().unwrap().get_host(&auth)?;
+                    game.kick(host, query.seat.ok_or(ServerError::PathError)?)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/action/123abc/?action=dscd[&validate=true] (with basic Auth)
+        // With `validate` the action is only checked, the response tells
+        // whether it is valid and why not.
+        let action = path("action")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: ActionQuery| async move {
+                    println!("{},{:?}", auth, &query);
+                    let inner = game.inner.lock().unwrap();
+                    let player = inner.get_player(&auth).ok_or(ServerError::InvalidAuth)?;
+                    drop(inner);
+                    if query.validate.unwrap_or(false) {
+                        let error = game.validate_player_action(player, query.action).err();
+                        let validation = Validation {
+                            valid: error.is_none(),
+                            error,
+                        };
+                        let result: Result<_, Rejection> =
+                            Ok(warp::reply::json(&validation).into_response());
+                        return result;
+                    }
+                    let checked = game.inner.lock().unwrap().check_may_act(player);
+                    match checked {
+                        Ok(()) => {
+                            game.perform_player_action(player, query.action);
+                        }
+                        Err(err) => warn!("Rejected player action: {}", err),
+                    }
+
+                    let result: Result<_, Rejection> = Ok("success".into_response());
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/actions/123abc/?actions=actp:1sj,actp:1s9,dscd: (with basic Auth)
+        // Performs the whole batch or nothing, the response tells which
+        // action failed and why.
+        let actions = path("actions")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: ActionsQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    let player = player.ok_or(ServerError::InvalidAuth)?;
+                    let batch = match game.perform_player_actions(player, query.actions) {
+                        Ok(()) => Batch {
+                            applied: true,
+                            failed_action: None,
+                            error: None,
+                        },
+                        Err((index, error)) => Batch {
+                            applied: false,
+                            failed_action: Some(index),
+                            error: Some(error),
+                        },
+                    };
+                    let result: Result<_, Rejection> = Ok(warp::reply::json(&batch));
+                    result
+                },
+            );
+
+        // POST server.com/api/v0/game/vote/123abc/?vote=abrt or ?vote=end (with basic Auth)
+        // Omitting the vote withdraws it.
+        let vote = path("vote")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(
+                |game: Arc<Game>, auth: String, query: VoteQuery| async move {
+                    let player = game.inner.lock().unwrap().get_player(&auth);
+                    game.vote(player.ok_or(ServerError::InvalidAuth)?, query.vote)?;
+                    let result: Result<&'static str, Rejection> = Ok("success");
+                    result
+                },
+            );
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/game/rematch/123abc/[?rotate=true] (with basic Auth)
+        // Responds with the id of the new game and new credentials on the next line.
+        let rematch = path("rematch")
+            .and(self.get_game_filter())
+            .and(path::end())
+            .and(warp::post())
+            .and(self.auth_filter())
+            .and(query())
+            .and_then(move |game: Arc<Game>, auth: String, query: RematchQuery| {
+                let player = game.inner.lock().unwrap().get_player(&auth);
+                let result = player
+                    .ok_or(ServerError::InvalidAuth)
+                    .and_then(|player| self2.rematch(&game, player, query.rotate.unwrap_or(false)))
+                    .map(|(id, credentials)| format!("{:016x}\n{}", id, credentials))
+                    .map_err(Rejection::from);
+                future::ready(result)
+            });
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/queue/?size=2[&ai_difficulty=hard][&name=Alice]
+        //     (optionally with bearer token)
+        // Streams `table:{id}:{credentials}` once there is a table, leaving
+        // the queue is done by closing the stream.
+        let queue = path!("queue")
+            .and(warp::post())
+            .and(query())
+            .and(self.identity_filter())
+            .and_then(move |query: QueueQuery, identity| {
+                let result = self2.enqueue(&query, identity).map(|(guard, receiver)| {
+                    let events = stream::once(receiver).map(move |table| {
+                        let _guard = &guard;
+                        match table {
+                            Ok((id, credentials)) => {
+                                Ok(sse::data(format!("table:{:016x}:{}", id, credentials)))
+                            }
+                            Err(_) => Err(ServerError::InternalError),
+                        }
+                    });
+                    sse::reply(
+                        sse::keep_alive()
+                            .interval(self2.config.keep_alive_interval)
+                            .stream(events),
+                    )
+                });
+                future::ready(result.map_err(Rejection::from))
+            });
+
+        let self2 = self.clone();
+        // GET server.com/api/v0/leaderboard/?offset=0&limit=20
+        let leaderboard = path!("leaderboard")
+            .and(warp::get())
+            .and(query())
+            .map(move |query: LeaderboardQuery| warp::reply::json(&self2.leaderboard(&query)));
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/account/register/ (with basic Auth of the username and the
+        //     password, an empty password for an account only logged in to by token)
+        // Responds with the account id and a token for it on the next line.
+        let register = path!("account" / "register")
+            .and(warp::post())
+            .and(self.account_auth_filter())
+            .and_then(move |username: String, password: String| {
+                let result = parse_name(&username)
+                    .and_then(|username| {
+                        let password = Some(password.as_str()).filter(|p| !p.is_empty());
+                        Ok(self2.accounts.register(&username, password)?)
+                    })
+                    .map(|(id, token)| format!("{}\n{}", id, token))
+                    .map_err(Rejection::from);
+                future::ready(result)
+            });
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/account/login/ (with basic Auth of the username and the password)
+        // Responds with the account id and a new token on the next line.
+        let login = path!("account" / "login")
+            .and(warp::post())
+            .and(self.account_auth_filter())
+            .and_then(move |username: String, password: String| {
+                let result = self2
+                    .accounts
+                    .login(&username, &password)
+                    .map(|(id, token)| format!("{}\n{}", id, token))
+                    .map_err(|err| Rejection::from(ServerError::from(err)));
+                future::ready(result)
+            });
+
+        let self2 = self.clone();
+        // POST server.com/api/v0/game/create/?ai_players=3[&turn_time=60&on_timeout=ai]
+        //     [&time_bank=600&increment=5&on_flag=resg][&private=true]
+        //     [&ai_difficulty=easy][&rated=true][&god_view_delay=120][&auto_start=true]
+        //     (optional Game-Password header, optionally with bearer token)
+        // Joining with the same bearer token makes the creator the host.
+        // Private games respond with the game id and an invite code on the next line.
+        let create = path!("create")
+            .and(warp::post())
+            .and(query())
+            .and(warp::header::optional("Game-Password"))
+            .and(self.identity_filter())
+            .and_then(
+                move |query: CreateQuery, password: Option<String>, identity| {
+                    let settings = GameSettings {
+                        turn_time: query.turn_time.map(Duration::from_secs),
+                        on_timeout: query.on_timeout.unwrap_or(TimeoutPolicy::Discard),
+                        time_bank: query.time_bank.map(Duration::from_secs),
+                        increment: Duration::from_secs(query.increment.unwrap_or(0)),
+                        on_flag: query.on_flag.unwrap_or(FlagPenalty::Discard),
+                        private: query.private.unwrap_or(false),
+                        password,
+                        ai_difficulty: query.ai_difficulty.unwrap_or_default(),
+                        rated: query.rated.unwrap_or(false),
+                        god_view_delay: query.god_view_delay.map(Duration::from_secs),
+                        auto_start: query.auto_start.unwrap_or(false),
+                    };
+                    // A short delay would let observers help the players.
+                    let min_delay = self2.config.min_god_view_delay;
+                    if settings
+                        .god_view_delay
+                        .is_some_and(|delay| delay < min_delay)
+                    {
+                        return future::ready(Err(Rejection::from(ServerError::InvalidState)));
+                    }
+                    let (id, game) = self2.create_game(query.ai_players, settings, identity);
+                    let response = if game.inner.lock().unwrap().settings.private {
+                        format!("{:016x}\n{}", id, game.create_invite())
+                    } else {
+                        format!("{:016x}", id)
+                    };
+                    future::ready(Ok(response))
+                },
+            );
+
+        let self2 = self.clone();
+        // GET server.com/api/v0/games/?min_free_seats=2&variant=standard&offset=0&limit=20
+        let games = path!("games")
+            .and(warp::get())
+            .and(query())
+            .map(move |query: LobbyQuery| warp::reply::json(&self2.list_games(&query)));
+
+        let self2 = self.clone();
+        // GET server.com/api/v0/history/?variant=standard&offset=0&limit=20
+        let history = path!("history")
+            .and(warp::get())
+            .and(query())
+            .map(move |query: HistoryQuery| warp::reply::json(&self2.list_history(&query)));
+
+        let game = path("game").and(
+            stream
+                .or(join)
+                .or(action)
+                .or(actions)
+                .or(create_invite)
+                .or(revoke_invite)
+                .or(ready)
+                .or(add_ai)
+                .or(remove_ai)
+                .or(start)
+                .or(leave)
+                .or(kick)
+                .or(vote)
+                .or(rematch)
+                .or(watch)
+                .or(spectators)
+                .or(god_view)
+                .or(create_observer_key)
+                .or(chat)
+                .or(emote)
+                .or(spectator_chat)
+                .or(mute)
+                .or(hint)
+                .or(hints)
+                .or(attack_preview),
+        );
+
+        path!("api" / "v0" / ..).and(
+            game.or(create)
+                .or(games)
+                .or(history)
+                .or(queue)
+                .or(register)
+                .or(login)
+                .or(leaderboard),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateQuery {
+    ai_players: u8,
+    /// Seconds every player has for their turn.
+    turn_time: Option<u64>,
+    on_timeout: Option<TimeoutPolicy>,
+    /// Seconds on every player's clock.
+    time_bank: Option<u64>,
+    increment: Option<u64>,
+    on_flag: Option<FlagPenalty>,
+    private: Option<bool>,
+    ai_difficulty: Option<Difficulty>,
+    rated: Option<bool>,
+    /// Seconds the god view lags behind.
+    god_view_delay: Option<u64>,
+    auto_start: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct JoinQuery {
+    name: Option<String>,
+    seat: Option<usize>,
+    #[serde(default, deserialize_with = "str_to_opt_suit")]
+    suit: Option<Suit>,
+    invite: Option<String>,
+    /// Taken from the `Game-Password` header, so it doesn't end up in logs.
+    #[serde(skip)]
+    password: Option<String>,
+    /// Mixed into the seed the game is dealt with.
+    seed: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InviteQuery {
+    invite: String,
+}
+
+#[derive(Deserialize)]
+struct ReadyQuery {
+    ready: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct SeatQuery {
+    seat: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct VoteQuery {
+    vote: Option<Vote>,
+}
+
+#[derive(Deserialize)]
+struct ChatQuery {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct EmoteQuery {
+    emote: Emote,
+}
+
+#[derive(Deserialize)]
+struct SpectatorChatQuery {
+    name: String,
+    text: String,
+    invite: Option<String>,
+    /// Taken from the `Game-Password` header, so it doesn't end up in logs.
+    #[serde(skip)]
+    password: Option<String>,
+}
 
-        let player = inner.get_player(auth).ok_or(ServerError::InvalidAuth)?;
+#[derive(Deserialize)]
+struct AttackQuery {
+    #[serde(deserialize_with = "str_to_house_pile")]
+    house_pile: HousePile,
+    #[serde(deserialize_with = "str_to_suit")]
+    target: Suit,
+}
 
-        let state = inner.state.clone();
-        drop(inner);
+/// What an attack would do, `transferred` are the cards changing hands in
+/// the format of a pile.
+#[derive(Serialize)]
+struct AttackPreviewEntry {
+    defending_pile: Option<String>,
+    attack_value: u32,
+    defense_value: Option<u32>,
+    outcome: &'static str,
+    transferred: String,
+}
 
-        let event_stream = game.notify_change.subscribe();
-        let state_stream = stream::once(async move { Ok(GameEvent::GameStateChanged(state)) });
-        let both = stream::select(state_stream, event_stream);
+#[derive(Deserialize)]
+struct HintsQuery {
+    allowed: bool,
+}
 
-        Ok(sse::reply(both.map(move |event| match event {
-            Ok(event) => Ok(sse::data(format!("{}", event.to_string(player)))),
-            Err(_) => Err(ServerError::InternalError),
-        })))
-    }
+#[derive(Deserialize)]
+struct MuteQuery {
+    seat: usize,
+    muted: Option<bool>,
+}
 
-    fn create_game(&self, ai_player_count: u8) -> u64 {
-        let game = Game::new(ai_player_count as usize);
-        game.check_start_game();
-        loop {
-            let id = rand::random();
-            match self.games.write().unwrap().entry(id) {
-                Entry::Occupied(_) => continue,
-                Entry::Vacant(v) => v.insert(game),
-            };
-            break id;
-        }
-    }
+#[derive(Deserialize)]
+struct RematchQuery {
+    rotate: Option<bool>,
+}
 
-    pub async fn serve(&self, addr: impl Into<SocketAddr> + 'static) {
-        let log = warp::log("web_api");
+#[derive(Deserialize)]
+struct QueueQuery {
+    /// Amount of human players at the table.
+    size: Option<usize>,
+    ai_difficulty: Option<Difficulty>,
+    name: Option<String>,
+}
 
-        // GET server.com/api/v0/game/stream/123abc/ (with basic Auth)
-        let stream = path("stream")
-            .and(self.get_game_filter())
-            .and(path::end())
-            .and(warp::get())
-            .and(self.auth_filter())
-            .and_then(|x, auth: String| async move { Server::map_game_event_stream(x, &auth) });
-        // POST server.com/api/v0/game/join/123abc/
-        let join = path("join")
-            .and(self.get_game_filter())
-            .and(path::end())
-            .and(warp::post())
-            .map(|game: Arc<Game>| game.join_player().unwrap_or("Error".to_string()));
+#[derive(Deserialize)]
+struct WatchQuery {
+    invite: Option<String>,
+    /// Taken from the `Game-Password` header, so it doesn't end up in logs.
+    #[serde(skip)]
+    password: Option<String>,
+}
 
-        // POST server.com/api/v0/game/action/123abc/?action=dscd (with basic Auth)
-        let action = path("action")
-            .and(self.get_game_filter())
-            .and(path::end())
-            .and(warp::post())
-            .and(self.auth_filter())
-            .and(query())
-            .and_then(
-                |game: Arc<Game>, auth: String, query: ActionQuery| async move {
-                    println!("{},{:?}", auth, &query);
-                    let inner = game.inner.lock().unwrap();
-                    let player = inner.get_player(&auth).ok_or(ServerError::InvalidAuth)?;
-                    drop(inner);
-                    game.perform_player_action(player, query.action);
+#[derive(Deserialize)]
+struct ObserverQuery {
+    key: String,
+}
 
-                    let result: Result<&'static str, Rejection> = Ok("success");
-                    result
-                },
-            );
+#[derive(Deserialize)]
+struct SpectatorsQuery {
+    allowed: bool,
+}
 
-        let self2 = self.clone();
-        // POST server.com/api/v0/game/create/?ai_players=3
-        let create = path!("create")
-            .and(warp::post())
-            .and(query())
-            .map(move |query: CreateQuery| format!("{:016x}", self2.create_game(query.ai_players)));
+#[derive(Deserialize)]
+struct LobbyQuery {
+    min_free_seats: Option<usize>,
+    variant: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
 
-        let game = path("game").and(stream.or(join).or(action));
+#[derive(Debug, Serialize)]
+struct LobbyEntry {
+    id: String,
+    seats_taken: usize,
+    seats_free: usize,
+    ai_players: usize,
+    /// Unix timestamp in seconds.
+    created: u64,
+    variant: String,
+}
 
-        let api = path!("api" / "v0" / ..).and(game.or(create)).with(log);
+#[derive(Deserialize)]
+struct HistoryQuery {
+    variant: Option<String>,
+    account: Option<u64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
 
-        warp::serve(api).run(addr).await;
-    }
+#[derive(Debug, Serialize)]
+struct HistoryEntry {
+    id: String,
+    variant: String,
+    /// The result and the ranking, as in the `gmend` event.
+    result: String,
+    names: Vec<String>,
+    /// Account ids of the players, per seat.
+    accounts: Vec<Option<u64>>,
+    /// Unix timestamp in seconds.
+    finished: u64,
 }
 
 #[derive(Deserialize)]
-struct CreateQuery {
-    ai_players: u8,
+struct LeaderboardQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct LeaderboardEntry {
+    name: String,
+    /// Account id, `None` for AI players.
+    account: Option<u64>,
+    rating: i64,
+    games: u32,
 }
 
 #[derive(Debug, Deserialize)]
 struct ActionQuery {
     #[serde(deserialize_with = "str_to_player_action")]
     action: PlayerAction,
+    validate: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ActionsQuery {
+    #[serde(deserialize_with = "str_to_player_actions")]
+    actions: Vec<PlayerAction>,
+}
+
+/// Whether a batch of actions was performed, with the first one that failed
+/// and why.
+#[derive(Serialize)]
+struct Batch {
+    applied: bool,
+    failed_action: Option<usize>,
+    error: Option<&'static str>,
+}
+
+/// Whether an action would succeed, with the reason if it wouldn't.
+#[derive(Serialize)]
+struct Validation {
+    valid: bool,
+    error: Option<&'static str>,
 }
 
 fn str_to_player_action<'de, D>(deserializer: D) -> Result<PlayerAction, D::Error>
@@ -346,3 +3145,858 @@ where
     println!("{:?}", &s);
     FromStr::from_str(&s).map_err(|_| de::Error::custom("Error while deserializing PlayerAction"))
 }
+
+/// Parses at least one action, separated by commas.
+fn str_to_player_actions<'de, D>(deserializer: D) -> Result<Vec<PlayerAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    if s.is_empty() {
+        Err(de::Error::custom("No PlayerAction given"))?
+    }
+    s.split(',')
+        .map(FromStr::from_str)
+        .collect::<Result<_, _>>()
+        .map_err(|_| de::Error::custom("Error while deserializing PlayerAction"))
+}
+
+fn str_to_suit<'de, D>(deserializer: D) -> Result<Suit, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    FromStr::from_str(&s).map_err(|_| de::Error::custom("Error while deserializing Suit"))
+}
+
+fn str_to_house_pile<'de, D>(deserializer: D) -> Result<HousePile, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    FromStr::from_str(&s).map_err(|_| de::Error::custom("Error while deserializing HousePile"))
+}
+
+fn str_to_opt_suit<'de, D>(deserializer: D) -> Result<Option<Suit>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    FromStr::from_str(&s)
+        .map(Some)
+        .map_err(|_| de::Error::custom("Error while deserializing Suit"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cards::{Card, Rank, SpecialPile};
+
+    fn empty_join_query() -> JoinQuery {
+        JoinQuery {
+            name: None,
+            seat: None,
+            suit: None,
+            invite: None,
+            password: None,
+            seed: None,
+        }
+    }
+
+    fn new_game(ai_player_count: usize, settings: GameSettings) -> Arc<Game> {
+        Game::new(
+            ai_player_count,
+            settings,
+            &Default::default(),
+            &Default::default(),
+        )
+    }
+
+    /// Seats a human player on every free seat of `game` and starts it,
+    /// returns their credentials.
+    fn start_with_humans(game: &Arc<Game>) -> Vec<String> {
+        let mut credentials = Vec::new();
+        for player in 0..4 {
+            if game.inner.lock().unwrap().seats[player].is_none() {
+                credentials.push(game.join_player(&empty_join_query(), None).unwrap());
+                game.set_ready(player, true).unwrap();
+            }
+        }
+        game.start_game().unwrap();
+        credentials
+    }
+
+    /// A running game of four human players.
+    fn started_game(settings: GameSettings) -> (Arc<Game>, Vec<String>) {
+        let game = new_game(0, settings);
+        let credentials = start_with_humans(&game);
+        (game, credentials)
+    }
+
+    #[test]
+    fn it_replays_logged_events() {
+        let game = new_game(0, Default::default());
+        for _ in 0..EVENT_LOG_SIZE + 10 {
+            game.broadcast(GameEvent::GameWon(0));
+        }
+        let inner = game.inner.lock().unwrap();
+        let last = inner.last_event_id;
+
+        assert_eq!(inner.events_since(last).unwrap().len(), 0);
+        let events = inner.events_since(last - 3).unwrap();
+        assert_eq!(
+            events.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![last - 2, last - 1, last]
+        );
+        assert!(inner.events_since(last - EVENT_LOG_SIZE as u64).is_some());
+        assert!(inner.events_since(last - EVENT_LOG_SIZE as u64 - 1).is_none());
+        assert!(inner.events_since(last + 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn it_hands_disconnected_seats_to_ai() {
+        let config = Arc::new(ServerConfig {
+            takeover_grace_period: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let game = Game::new(3, Default::default(), &config, &Default::default());
+        let query = empty_join_query();
+        game.join_player(&query, None).unwrap();
+        game.set_ready(3, true).unwrap();
+        game.start_game().unwrap();
+
+        let guard = game.connect(&mut game.inner.lock().unwrap(), 3);
+        drop(guard);
+        assert!(!game.inner.lock().unwrap().is_ai_controlled(3));
+        delay_for(Duration::from_millis(50)).await;
+        assert!(game.inner.lock().unwrap().is_ai_controlled(3));
+
+        let _guard = game.connect(&mut game.inner.lock().unwrap(), 3);
+        assert!(!game.inner.lock().unwrap().is_ai_controlled(3));
+
+        // Players who never open a stream are taken over on their turn.
+        let game = Game::new(0, Default::default(), &config, &Default::default());
+        start_with_humans(&game);
+        delay_for(Duration::from_millis(50)).await;
+        assert!(game.inner.lock().unwrap().is_ai_controlled(0));
+        assert!(!game.inner.lock().unwrap().is_ai_controlled(1));
+    }
+
+    #[tokio::test]
+    async fn it_ends_turns_on_timeout() {
+        let settings = GameSettings {
+            turn_time: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (game, _) = started_game(settings);
+        assert!(game.inner.lock().unwrap().turn_deadline.is_some());
+        delay_for(Duration::from_millis(30)).await;
+        assert_eq!(game.inner.lock().unwrap().state.round_state.player, 1);
+
+        // The stand-in taking over the seat mid-turn is left to finish it.
+        game.inner.lock().unwrap().stand_ins[1] = Some(AIPlayer::new(1));
+        delay_for(Duration::from_millis(30)).await;
+        assert_eq!(game.inner.lock().unwrap().state.round_state.player, 1);
+    }
+
+    #[tokio::test]
+    async fn it_penalizes_running_out_of_time() {
+        let settings = |on_flag| GameSettings {
+            time_bank: Some(Duration::from_millis(100)),
+            increment: Duration::from_secs(10),
+            on_flag,
+            ..Default::default()
+        };
+        let (game, _) = started_game(settings(FlagPenalty::Discard));
+        game.perform_player_action(0, PlayerAction::DiscardHand);
+        assert!(game.inner.lock().unwrap().clocks[0] > Duration::from_secs(10));
+        delay_for(Duration::from_millis(150)).await;
+        {
+            let inner = game.inner.lock().unwrap();
+            assert_eq!(inner.state.round_state.player, 2);
+            assert!(!inner.state.players[1].forfeited);
+        }
+
+        let (game, _) = started_game(settings(FlagPenalty::Forfeit));
+        delay_for(Duration::from_millis(150)).await;
+        assert!(game.inner.lock().unwrap().state.players[0].forfeited);
+
+        // A stand-in taking over mid-turn stops the clock.
+        let config = Arc::new(ServerConfig {
+            takeover_grace_period: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let game = Game::new(
+            0,
+            settings(FlagPenalty::Forfeit),
+            &config,
+            &Default::default(),
+        );
+        start_with_humans(&game);
+        let guard = game.connect(&mut game.inner.lock().unwrap(), 0);
+        drop(guard);
+        delay_for(Duration::from_millis(150)).await;
+        let inner = game.inner.lock().unwrap();
+        assert!(inner.is_ai_controlled(0));
+        assert!(!inner.state.players[0].forfeited);
+        assert!(inner.clocks[0] > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn it_lists_joinable_games() {
+        let server = Server::new();
+        let (open, game) = server.create_game(0, Default::default(), None);
+        game.join_player(&empty_join_query(), None).unwrap();
+        let (with_ai, _) = server.create_game(2, Default::default(), None);
+        let settings = GameSettings {
+            private: true,
+            ..Default::default()
+        };
+        server.create_game(0, settings, None);
+        let (_, game) = server.create_game(3, Default::default(), None);
+        start_with_humans(&game);
+
+        let query = |min_free_seats, variant: Option<&str>, offset, limit| LobbyQuery {
+            min_free_seats,
+            variant: variant.map(str::to_owned),
+            offset,
+            limit,
+        };
+        let ids = |entries: Vec<LobbyEntry>| -> Vec<String> {
+            let mut ids: Vec<_> = entries.into_iter().map(|e| e.id).collect();
+            ids.sort();
+            ids
+        };
+        let mut all = vec![format!("{:016x}", open), format!("{:016x}", with_ai)];
+        all.sort();
+        assert_eq!(ids(server.list_games(&query(None, None, None, None))), all);
+        let entries = server.list_games(&query(Some(3), None, None, None));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, format!("{:016x}", open));
+        assert_eq!((entries[0].seats_taken, entries[0].seats_free), (1, 3));
+        let entries = server.list_games(&query(Some(2), None, None, None));
+        let entry = entries.iter().find(|e| e.id == format!("{:016x}", with_ai));
+        assert_eq!(entry.unwrap().ai_players, 2);
+
+        let variant = GameSettings::default().variant();
+        assert_eq!(
+            ids(server.list_games(&query(None, Some(&variant), None, None))),
+            all
+        );
+        assert!(server
+            .list_games(&query(None, Some("turn1s"), None, None))
+            .is_empty());
+        let first = server.list_games(&query(None, None, None, Some(1)));
+        let second = server.list_games(&query(None, None, Some(1), Some(1)));
+        assert_eq!(ids(first.into_iter().chain(second).collect()), all);
+        assert!(server
+            .list_games(&query(None, None, Some(2), None))
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_needs_an_invite_or_password_for_private_games() {
+        let server = Server::new();
+        let settings = GameSettings {
+            private: true,
+            password: Some("secret".to_owned()),
+            ..Default::default()
+        };
+        let (id, game) = server.create_game(0, settings, None);
+        let query = |invite: Option<&str>, password: Option<&str>| JoinQuery {
+            invite: invite.map(str::to_owned),
+            password: password.map(str::to_owned),
+            ..empty_join_query()
+        };
+        assert!(matches!(
+            game.join_player(&query(None, None), None),
+            Err(ServerError::InvalidAuth)
+        ));
+        assert!(game.join_player(&query(None, Some("wrong")), None).is_err());
+        let host = game
+            .join_player(&query(None, Some("secret")), None)
+            .unwrap();
+        let invite = game.create_invite();
+        let guest = game.join_player(&query(Some(&invite), None), None).unwrap();
+
+        let routes = server.routes();
+        let revoke = |credentials: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!(
+                    "/api/v0/game/invite/{:016x}/?invite={}",
+                    id, invite
+                ))
+                .header(
+                    "Authorization",
+                    format!("Basic {}", base64::encode(credentials)),
+                )
+                .reply(&routes)
+        };
+        assert_eq!(revoke(&guest).await.status(), 500);
+        assert!(game.join_player(&query(Some(&invite), None), None).is_ok());
+        assert_eq!(revoke(&host).await.status(), 200);
+        assert!(game.join_player(&query(Some(&invite), None), None).is_err());
+    }
+
+    #[test]
+    fn it_seats_players_by_name_and_suit() {
+        let game = new_game(0, Default::default());
+        let alice = JoinQuery {
+            name: Some("Alice".to_owned()),
+            seat: Some(2),
+            ..empty_join_query()
+        };
+        assert!(game.join_player(&alice, None).unwrap().starts_with("2:"));
+        let suit = game.inner.lock().unwrap().state.players[1].suit;
+        let bob = JoinQuery {
+            name: Some("Bob".to_owned()),
+            suit: Some(suit),
+            ..empty_join_query()
+        };
+        assert!(game.join_player(&bob, None).unwrap().starts_with("1:"));
+        let carol = JoinQuery {
+            name: Some("Carol".to_owned()),
+            seat: Some(2),
+            ..empty_join_query()
+        };
+        assert!(game.join_player(&carol, None).unwrap().starts_with("0:"));
+        let invalid = JoinQuery {
+            name: Some("Dave\nEve".to_owned()),
+            ..empty_join_query()
+        };
+        assert!(matches!(
+            game.join_player(&invalid, None),
+            Err(ServerError::InvalidName)
+        ));
+
+        let snapshot = game.inner.lock().unwrap().snapshot();
+        let s = GameEvent::GameStateChanged(snapshot).to_string(None);
+        assert!(s.contains("\nnames:Carol,Bob,Alice,\n"));
+    }
+
+    #[tokio::test]
+    async fn it_hands_left_seats_to_ai_and_new_players() {
+        let game = new_game(0, Default::default());
+        for _ in 0..3 {
+            game.join_player(&empty_join_query(), None).unwrap();
+        }
+        assert!(game.kick(0, 0).is_err());
+        assert!(game.kick(1, 2).is_ok());
+        assert!(game.inner.lock().unwrap().seats[2].is_none());
+
+        let (game, _) = started_game(Default::default());
+        assert!(game.kick(0, 2).is_err());
+        game.leave(2);
+        {
+            let inner = game.inner.lock().unwrap();
+            assert!(inner.is_ai_controlled(2));
+            assert_eq!(inner.open_seat(None), Some(2));
+            assert_eq!(inner.state.players.len(), 4);
+        }
+        let credentials = game.join_player(&empty_join_query(), None).unwrap();
+        assert!(credentials.starts_with("2:"));
+        let inner = game.inner.lock().unwrap();
+        assert!(inner.is_human(2));
+        assert_eq!(inner.open_seat(None), None);
+    }
+
+    #[tokio::test]
+    async fn it_starts_once_the_host_does() {
+        let game = new_game(2, Default::default());
+        let query = empty_join_query();
+        game.join_player(&query, None).unwrap();
+        assert!(game.start_game().is_err());
+        game.set_ready(2, true).unwrap();
+        game.start_game().unwrap();
+        let inner = game.inner.lock().unwrap();
+        assert!(inner.is_started);
+        assert!(inner.seats.iter().all(Option::is_some));
+        drop(inner);
+
+        let settings = GameSettings {
+            auto_start: true,
+            ..Default::default()
+        };
+        let game = new_game(3, settings);
+        game.join_player(&query, None).unwrap();
+        assert!(!game.inner.lock().unwrap().is_started);
+        game.set_ready(3, true).unwrap();
+        assert!(game.inner.lock().unwrap().is_started);
+
+        let server = Server::new();
+        let creator = Identity {
+            id: 1,
+            username: "Alice".to_owned(),
+        };
+        let (_, game) = server.create_game(2, Default::default(), Some(creator.clone()));
+        game.join_player(&query, None).unwrap();
+        assert_eq!(game.inner.lock().unwrap().host, None);
+        game.join_player(&query, Some(creator)).unwrap();
+        assert_eq!(game.inner.lock().unwrap().host, Some(3));
+    }
+
+    #[tokio::test]
+    async fn it_ends_games_by_vote() {
+        let game = new_game(2, Default::default());
+        let query = empty_join_query();
+        for _ in 0..2 {
+            game.join_player(&query, None).unwrap();
+        }
+        assert!(game.vote(2, Some(Vote::End)).is_err());
+        game.set_ready(2, true).unwrap();
+        game.set_ready(3, true).unwrap();
+        game.start_game().unwrap();
+
+        game.vote(2, Some(Vote::End)).unwrap();
+        assert!(game.inner.lock().unwrap().outcome.is_none());
+        game.vote(3, Some(Vote::Abort)).unwrap();
+        assert!(game.inner.lock().unwrap().outcome.is_none());
+        game.vote(3, Some(Vote::End)).unwrap();
+
+        let inner = game.inner.lock().unwrap();
+        let outcome = inner.outcome.as_ref().unwrap();
+        assert_eq!(outcome.result, GameResult::Ended);
+        assert_eq!(outcome.ranking.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn it_rates_ai_players_once_per_game() {
+        let settings = GameSettings {
+            rated: true,
+            ..Default::default()
+        };
+        let game = new_game(3, settings);
+        let query = empty_join_query();
+        let identity = Identity {
+            id: 1,
+            username: "Alice".to_owned(),
+        };
+        game.join_player(&query, Some(identity)).unwrap();
+        game.set_ready(3, true).unwrap();
+        game.start_game().unwrap();
+        assert!(matches!(
+            game.vote(3, Some(Vote::End)),
+            Err(ServerError::Forbidden)
+        ));
+
+        game.end_game(&mut game.inner.lock().unwrap(), GameResult::Ended);
+        let leaderboard = game.ratings.leaderboard();
+        assert_eq!(leaderboard.len(), 2);
+        assert!(leaderboard.iter().all(|r| r.games == 1));
+    }
+
+    #[tokio::test]
+    async fn it_rates_players_who_leave_last() {
+        let settings = GameSettings {
+            rated: true,
+            ..Default::default()
+        };
+        let game = new_game(2, settings.clone());
+        for id in 1..3 {
+            let identity = Identity {
+                id,
+                username: format!("Player{}", id),
+            };
+            game.join_player(&empty_join_query(), Some(identity))
+                .unwrap();
+        }
+        game.set_ready(2, true).unwrap();
+        game.set_ready(3, true).unwrap();
+        game.start_game().unwrap();
+        game.leave(2);
+        game.end_game(&mut game.inner.lock().unwrap(), GameResult::Ended);
+        let leaderboard = game.ratings.leaderboard();
+        assert_eq!(leaderboard.len(), 3);
+        assert_eq!(leaderboard[2].entity, Entity::Player(1));
+
+        // Aborting leaves the ratings as they are.
+        let game = new_game(3, settings);
+        game.join_player(&empty_join_query(), None).unwrap();
+        game.set_ready(3, true).unwrap();
+        game.start_game().unwrap();
+        game.vote(3, Some(Vote::Abort)).unwrap();
+        let outcome = game.inner.lock().unwrap().outcome.clone();
+        assert_eq!(outcome.unwrap().result, GameResult::Aborted);
+        assert!(game.ratings.leaderboard().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_rematches_with_new_credentials() {
+        let server = Server::new();
+        let (_, game) = server.create_game(0, Default::default(), None);
+        let old = start_with_humans(&game);
+        assert!(server.rematch(&game, 0, true).is_err());
+        game.end_game(&mut game.inner.lock().unwrap(), GameResult::Ended);
+
+        let (id, credentials) = server.rematch(&game, 0, true).unwrap();
+        assert_ne!(credentials, old[0]);
+        let (same_id, other) = server.rematch(&game, 1, true).unwrap();
+        assert_eq!(same_id, id);
+        assert!(other.starts_with("1:"));
+
+        let rematch = server.games.read().unwrap()[&id].clone();
+        let inner = rematch.inner.lock().unwrap();
+        assert_eq!(inner.first_player, 1);
+        assert_eq!(inner.get_player(&base64::encode(&credentials)), Some(0));
+        assert_eq!(inner.get_player(&base64::encode(&old[0])), None);
+        assert!(!inner.is_started);
+    }
+
+    #[tokio::test]
+    async fn it_shows_spectators_no_hands() {
+        let server = Server::new();
+        let (_, game) = server.create_game(0, Default::default(), None);
+        start_with_humans(&game);
+        let snapshot = game.inner.lock().unwrap().snapshot();
+        let event = GameEvent::GameStateChanged(snapshot);
+        assert!(event.to_string(Some(0)).contains("\nhand:"));
+        assert!(!event.to_string(None).contains("\nhand:"));
+
+        let query = WatchQuery {
+            invite: None,
+            password: None,
+        };
+        let stream = server.map_spectator_stream(game.clone(), &query, None);
+        assert!(stream.is_ok());
+        assert_eq!(game.inner.lock().unwrap().spectator_count(), Some(1));
+        game.set_spectating(false);
+        assert_eq!(game.inner.lock().unwrap().spectator_count(), None);
+        assert!(server
+            .map_spectator_stream(game.clone(), &query, None)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_mutes_players_in_chat() {
+        let game = new_game(2, Default::default());
+        let query = empty_join_query();
+        for _ in 0..2 {
+            game.join_player(&query, None).unwrap();
+        }
+        assert!(game.chat(3, "hi\nthere").is_err());
+        game.chat(3, "hi there").unwrap();
+        assert!(game.mute(2, 2, true).is_err());
+        assert!(game.mute(2, 0, true).is_err());
+        game.mute(2, 3, true).unwrap();
+        assert!(game.chat(3, "hi").is_err());
+        assert!(game.emote(3, Emote::GoodGame).is_err());
+        game.emote(2, Emote::GoodGame).unwrap();
+
+        let event = GameEvent::SpectatorChat("Bob".to_owned(), "hi".to_owned());
+        assert!(event.is_visible_to(None));
+        assert!(!event.is_visible_to(Some(2)));
+    }
+
+    #[test]
+    fn it_throttles_spectator_chat() {
+        let game = new_game(0, Default::default());
+        let query = |text: &str| SpectatorChatQuery {
+            name: "Bob".to_owned(),
+            text: text.to_owned(),
+            invite: None,
+            password: None,
+        };
+        let addr = Some(IpAddr::from([127, 0, 0, 1]));
+        assert!(game
+            .spectator_chat(&query(&"a".repeat(MAX_SPECTATOR_CHAT_LEN + 1)), addr)
+            .is_err());
+        game.spectator_chat(&query("hi"), addr).unwrap();
+        assert!(game.spectator_chat(&query("hi"), addr).is_err());
+        game.spectator_chat(&query("hi"), Some(IpAddr::from([127, 0, 0, 2])))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_validates_actions_without_applying_them() {
+        let game = new_game(0, Default::default());
+        assert!(game
+            .validate_player_action(0, PlayerAction::DiscardHand)
+            .is_err());
+        start_with_humans(&game);
+        let last_event_id = game.inner.lock().unwrap().last_event_id;
+
+        assert_eq!(
+            game.validate_player_action(1, PlayerAction::DiscardHand),
+            Err("not your turn")
+        );
+        game.validate_player_action(0, PlayerAction::DiscardHand)
+            .unwrap();
+        let mut inner = game.inner.lock().unwrap();
+        assert_eq!(inner.state.round_state.player, 0);
+        assert_eq!(inner.last_event_id, last_event_id);
+
+        inner.stand_ins[0] = Some(AIPlayer::new(0));
+        drop(inner);
+        assert_eq!(
+            game.validate_player_action(0, PlayerAction::DiscardHand),
+            Err("an AI is playing for you")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_actions_on_a_flagged_clock() {
+        let settings = GameSettings {
+            time_bank: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let (game, _) = started_game(settings);
+        game.inner.lock().unwrap().clocks[0] = Duration::ZERO;
+        assert_eq!(
+            game.validate_player_action(0, PlayerAction::DiscardHand),
+            Err("your clock ran out")
+        );
+        assert_eq!(
+            game.perform_player_actions(0, vec![PlayerAction::DiscardHand]),
+            Err((0, "your clock ran out"))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_performs_batches_all_or_nothing() {
+        let (game, _) = started_game(Default::default());
+        let last_event_id = game.inner.lock().unwrap().last_event_id;
+
+        let batch = vec![PlayerAction::DiscardHand, PlayerAction::DiscardHand];
+        assert_eq!(
+            game.perform_player_actions(0, batch),
+            Err((1, "not your turn"))
+        );
+        assert_eq!(game.inner.lock().unwrap().state.round_state.player, 0);
+        assert_eq!(game.inner.lock().unwrap().last_event_id, last_event_id);
+
+        game.perform_player_actions(0, vec![PlayerAction::DiscardHand])
+            .unwrap();
+        {
+            let inner = game.inner.lock().unwrap();
+            assert_eq!(inner.state.round_state.player, 1);
+            assert_eq!(inner.last_event_id, last_event_id + 1);
+        }
+
+        let filter = warp::query::<ActionsQuery>();
+        let parse = |actions| {
+            warp::test::request()
+                .path(&format!("/?actions={}", actions))
+                .filter(&filter)
+        };
+        assert!(parse("").await.is_err());
+        assert!(parse("dscd:,").await.is_err());
+        assert_eq!(parse("dscd:").await.unwrap().actions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_publishes_seed_inputs_without_the_log() {
+        let game = new_game(0, Default::default());
+        let query = JoinQuery {
+            seed: Some("a".to_owned()),
+            ..empty_join_query()
+        };
+        for player in 0..4 {
+            game.join_player(&query, None).unwrap();
+            game.set_ready(player, true).unwrap();
+        }
+        game.start_game().unwrap();
+        game.perform_player_action(0, PlayerAction::DiscardHand);
+        game.leave(3);
+        game.join_player(&empty_join_query(), None).unwrap();
+
+        let inner = game.inner.lock().unwrap();
+        let seed = fairness::derive_seed(&inner.server_seed, &inner.seed_contributions);
+        assert_eq!(seed, inner.state.seed());
+        assert_eq!(inner.state.log.len(), 1);
+        let snapshot = inner.snapshot();
+        assert!(snapshot.state.log.is_empty());
+        let s = GameEvent::GameStateChanged(snapshot).to_string(None);
+        assert!(s.contains("\nseeds:61,61,61,61\n"));
+        assert!(s.contains(&format!("\ncmmit:{}", inner.commitment)));
+        assert!(!s.contains("sseed:"));
+    }
+
+    #[tokio::test]
+    async fn it_shows_observers_every_hand_with_a_delay() {
+        let server = Server::with_config(ServerConfig {
+            observer_secret: Some("secret".to_owned()),
+            ..Default::default()
+        });
+        let settings = GameSettings {
+            god_view_delay: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let (id, game) = server.create_game(0, settings, None);
+        let credentials = start_with_humans(&game);
+
+        let routes = server.routes();
+        let create_key = |auth: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/api/v0/game/observer/{:016x}/", id))
+                .header("Authorization", format!("Basic {}", base64::encode(auth)))
+                .reply(&routes)
+        };
+        let create = |delay: u64| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!(
+                    "/api/v0/create?ai_players=0&god_view_delay={}",
+                    delay
+                ))
+                .reply(&routes)
+        };
+        assert_eq!(create(0).await.status(), 500);
+        assert_eq!(create(60).await.status(), 200);
+        assert_eq!(create_key(&credentials[0]).await.status(), 500);
+        let response = create_key("secret").await;
+        assert_eq!(response.status(), 200);
+        let key = ObserverQuery {
+            key: String::from_utf8(response.body().to_vec()).unwrap(),
+        };
+        let seat = base64::encode(&credentials[1]);
+        assert!(server
+            .map_god_view_stream(game.clone(), &key, Some(&seat), None)
+            .is_err());
+        assert!(server
+            .map_god_view_stream(game.clone(), &key, None, None)
+            .is_ok());
+
+        let inner = game.inner.lock().unwrap();
+        let hand = inner.state.players[0].hand.to_string();
+        let s = god_view_to_string(&GameEvent::GameStateChanged(inner.snapshot()));
+        assert!(s.contains(&format!("\nhands:{}", hand)));
+        assert!(s.contains(&format!("\nstock:{:02}", inner.state.stock_pile.count())));
+    }
+
+    #[tokio::test]
+    async fn it_previews_attacks_over_http() {
+        let server = Server::new();
+        let (id, game) = server.create_game(0, Default::default(), None);
+        let credentials = start_with_humans(&game);
+        let target = game.inner.lock().unwrap().state.players[1].suit;
+        let routes = server.routes();
+        let preview = |player: usize| {
+            warp::test::request()
+                .path(&format!(
+                    "/api/v0/game/attack/{:016x}/?house_pile=1&target={}",
+                    id,
+                    target.to_string()
+                ))
+                .header(
+                    "Authorization",
+                    format!("Basic {}", base64::encode(&credentials[player])),
+                )
+                .reply(&routes)
+        };
+
+        let response = preview(1).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.body(),
+            r#"{"valid":false,"error":"not your turn"}"#
+        );
+        let response = preview(0).await;
+        assert_eq!(
+            response.body(),
+            r#"{"valid":false,"error":"chose non existent house pile to attack"}"#
+        );
+
+        let mut queens = SpecialPile::new(Card::new(Suit::Heart, Rank::Queen));
+        queens.cards.add(Card::new(Suit::Club, Rank::Five));
+        game.inner.lock().unwrap().state.players[0].house_pile_1 = Some(queens);
+        let response = preview(0).await;
+        assert_eq!(
+            response.body(),
+            r#"{"defending_pile":null,"attack_value":2,"defense_value":null,"outcome":"unopposed","transferred":"00"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn it_takes_account_passwords_from_basic_auth() {
+        let server = Server::new();
+        let routes = server.routes();
+        let request = |path: &str, credentials: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .header(
+                    "Authorization",
+                    format!("Basic {}", base64::encode(credentials)),
+                )
+                .reply(&routes)
+        };
+
+        let response = request("/api/v0/account/register/", "Alice:secret").await;
+        assert_eq!(response.status(), 200);
+        let id = String::from_utf8(response.body().to_vec()).unwrap();
+        let id = id.lines().next().unwrap().to_owned();
+        let response = request("/api/v0/account/login/", "alice:secret").await;
+        assert_eq!(response.status(), 200);
+        assert!(response.body().starts_with(id.as_bytes()));
+        let response = request("/api/v0/account/login/?password=secret", "alice:").await;
+        assert_eq!(response.status(), 500);
+        assert_eq!(
+            request("/api/v0/account/login/", "alice").await.status(),
+            500
+        );
+    }
+
+    #[tokio::test]
+    async fn it_fills_queued_tables_with_ai() {
+        let server = Server::with_config(ServerConfig {
+            queue_wait: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let query = QueueQuery {
+            size: Some(2),
+            ai_difficulty: None,
+            name: None,
+        };
+        let (_guard, receiver) = server.enqueue(&query, None).unwrap();
+        delay_for(Duration::from_millis(50)).await;
+
+        let (id, credentials) = receiver.await.unwrap();
+        let game = server.games.read().unwrap()[&id].clone();
+        let inner = game.inner.lock().unwrap();
+        assert!(inner.is_started);
+        assert!(inner.get_player(&base64::encode(&credentials)).is_some());
+        assert_eq!((0..4).filter(|p| inner.is_human(*p)).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_seats_ai_for_players_who_left_the_queue() {
+        let server = Server::new();
+        let identity = Identity {
+            id: 1,
+            username: "Alice".to_owned(),
+        };
+        let query = QueueQuery {
+            size: Some(2),
+            ai_difficulty: None,
+            name: None,
+        };
+        let _queued = server.enqueue(&query, Some(identity.clone())).unwrap();
+        assert!(server.enqueue(&query, Some(identity)).is_err());
+
+        let ticket = || {
+            let (sender, receiver) = oneshot::channel();
+            let ticket = QueueTicket {
+                id: rand::random(),
+                name: None,
+                identity: None,
+                size: 2,
+                ai_difficulty: Default::default(),
+                since: Instant::now(),
+                sender,
+            };
+            (ticket, receiver)
+        };
+        let (gone, _) = ticket();
+        let (waiting, receiver) = ticket();
+        server.form_table(vec![gone, waiting]);
+
+        let (id, credentials) = receiver.await.unwrap();
+        let game = server.games.read().unwrap()[&id].clone();
+        let inner = game.inner.lock().unwrap();
+        assert!(inner.is_started);
+        assert!(inner.get_player(&base64::encode(&credentials)).is_some());
+        assert_eq!((0..4).filter(|p| inner.is_human(*p)).count(), 1);
+    }
+}
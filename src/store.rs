@@ -0,0 +1,39 @@
+//! The JSON files accounts and ratings are kept in.
+
+use std::path::Path;
+use std::{fs, io};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Loads the records saved at `path`, none if the file doesn't exist yet.
+pub fn load<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    match fs::read(path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replaces the records saved at `path`.
+pub fn save<T: Serialize>(path: &Path, records: &[T]) -> io::Result<()> {
+    // Write a copy first, so a crash can't leave a broken file.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(records)?)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_saves_and_loads_records() {
+        let path = std::env::temp_dir().join(format!("store-{:016x}.json", rand::random::<u64>()));
+        assert!(load::<u64>(&path).unwrap().is_empty());
+        save(&path, &[1u64, 2, 3]).unwrap();
+        assert_eq!(load::<u64>(&path).unwrap(), vec![1, 2, 3]);
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_file(path).unwrap();
+    }
+}